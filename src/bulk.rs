@@ -0,0 +1,284 @@
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dto::{PlanetDto, SatelliteDto},
+    error::CustomResult,
+    model::{PlanetImage, PlanetType},
+};
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+const CSV_CONTENT_TYPE: &str = "text/csv";
+const CSV_HEADER: &str = "id,name,type,mean_radius,satellites,image\n";
+
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkFormat {
+    Json,
+    Ndjson,
+    Csv,
+}
+
+impl BulkFormat {
+    // only line-based formats can be imported
+    pub fn from_import_content_type(content_type: &str) -> Option<Self> {
+        let content_type = content_type.split(';').next()?.trim();
+
+        match content_type {
+            NDJSON_CONTENT_TYPE => Some(BulkFormat::Ndjson),
+            CSV_CONTENT_TYPE => Some(BulkFormat::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            BulkFormat::Json => mime::APPLICATION_JSON.as_ref(),
+            BulkFormat::Ndjson => NDJSON_CONTENT_TYPE,
+            BulkFormat::Csv => CSV_CONTENT_TYPE,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BulkFormat::Json => "json",
+            BulkFormat::Ndjson => "ndjson",
+            BulkFormat::Csv => "csv",
+        }
+    }
+
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            BulkFormat::Json => "[",
+            BulkFormat::Ndjson => "",
+            BulkFormat::Csv => CSV_HEADER,
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            BulkFormat::Json => "]",
+            BulkFormat::Ndjson | BulkFormat::Csv => "",
+        }
+    }
+
+    pub fn encode(&self, planet_dto: &PlanetDto, is_first: bool) -> CustomResult<Vec<u8>> {
+        let mut result = Vec::new();
+
+        match self {
+            BulkFormat::Json => {
+                if !is_first {
+                    result.push(b',');
+                }
+                serde_json::to_writer(&mut result, planet_dto)?;
+            }
+            BulkFormat::Ndjson => {
+                serde_json::to_writer(&mut result, planet_dto)?;
+                result.push(b'\n');
+            }
+            BulkFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(&mut result);
+                writer.serialize(PlanetCsvRecord::try_from(planet_dto)?)?;
+                writer.flush().map_err(csv::Error::from)?;
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+// satellites are kept as a JSON array in a single column, and an image as a JSON object
+#[derive(Serialize, Deserialize)]
+struct PlanetCsvRecord {
+    id: Option<String>,
+    name: String,
+    r#type: PlanetType,
+    mean_radius: f32,
+    satellites: Option<String>,
+    image: Option<String>,
+}
+
+impl TryFrom<&PlanetDto> for PlanetCsvRecord {
+    type Error = serde_json::Error;
+
+    fn try_from(planet_dto: &PlanetDto) -> Result<Self, Self::Error> {
+        let satellites = planet_dto
+            .satellites
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let image = planet_dto
+            .image
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+
+        Ok(Self {
+            id: planet_dto.id.clone(),
+            name: planet_dto.name.clone(),
+            r#type: planet_dto.r#type,
+            mean_radius: planet_dto.mean_radius,
+            satellites,
+            image,
+        })
+    }
+}
+
+impl TryFrom<PlanetCsvRecord> for PlanetDto {
+    type Error = serde_json::Error;
+
+    fn try_from(record: PlanetCsvRecord) -> Result<Self, Self::Error> {
+        let satellites = record
+            .satellites
+            .filter(|satellites| !satellites.trim().is_empty())
+            .map(|satellites| serde_json::from_str::<Vec<SatelliteDto>>(&satellites))
+            .transpose()?;
+        let image = record
+            .image
+            .filter(|image| !image.trim().is_empty())
+            .map(|image| serde_json::from_str::<PlanetImage>(&image))
+            .transpose()?;
+
+        Ok(Self {
+            id: record.id,
+            name: record.name,
+            r#type: record.r#type,
+            mean_radius: record.mean_radius,
+            satellites,
+            image,
+        })
+    }
+}
+
+pub struct PlanetRecordParser {
+    format: BulkFormat,
+    csv_header: Option<StringRecord>,
+}
+
+impl PlanetRecordParser {
+    pub fn new(format: BulkFormat) -> Self {
+        Self {
+            format,
+            csv_header: None,
+        }
+    }
+
+    // `None` is returned for lines without a record, i.e. blank lines and a CSV header
+    pub fn parse_line(&mut self, line: &str) -> Option<Result<PlanetDto, String>> {
+        if line.trim().is_empty() {
+            return None;
+        }
+
+        let planet_dto = match self.format {
+            BulkFormat::Json | BulkFormat::Ndjson => {
+                serde_json::from_str::<PlanetDto>(line).map_err(|err| err.to_string())
+            }
+            BulkFormat::Csv => {
+                let record = match Self::read_csv_record(line) {
+                    Ok(record) => record,
+                    Err(err) => return Some(Err(err)),
+                };
+
+                let Some(csv_header) = &self.csv_header else {
+                    self.csv_header = Some(record);
+                    return None;
+                };
+
+                // otherwise missing trailing columns would be read as empty ones
+                if record.len() != csv_header.len() {
+                    return Some(Err(format!(
+                        "Line has {} columns instead of {}",
+                        record.len(),
+                        csv_header.len()
+                    )));
+                }
+
+                record
+                    .deserialize::<PlanetCsvRecord>(Some(csv_header))
+                    .map_err(|err| err.to_string())
+                    .and_then(|record| PlanetDto::try_from(record).map_err(|err| err.to_string()))
+            }
+        };
+
+        Some(planet_dto.and_then(|planet_dto| planet_dto.validate().map(|_| planet_dto)))
+    }
+
+    fn read_csv_record(line: &str) -> Result<StringRecord, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(line.as_bytes());
+
+        let mut record = StringRecord::new();
+        reader
+            .read_record(&mut record)
+            .map_err(|err| err.to_string())?;
+
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_lines(format: BulkFormat, lines: &[&str]) -> Vec<Result<String, String>> {
+        let mut parser = PlanetRecordParser::new(format);
+
+        lines
+            .iter()
+            .filter_map(|line| parser.parse_line(line))
+            .map(|planet_dto| planet_dto.map(|planet_dto| planet_dto.name))
+            .collect()
+    }
+
+    #[test]
+    fn rejects_malformed_ndjson_lines() {
+        let results = parse_lines(
+            BulkFormat::Ndjson,
+            &[
+                r#"{"name": "Mars", "type": "TerrestrialPlanet", "mean_radius": 3389.5, "satellites": [{"name": "Phobos", "first_spacecraft_landing_date": null}]}"#,
+                "",
+                "   ",
+                r#"{"name": "Mars", "type": "TerrestrialPlanet""#,
+                r#"["Mars"]"#,
+                r#"{"name": "Mars", "type": "DwarfStar", "mean_radius": 3389.5, "satellites": null}"#,
+                r#"{"name": "Mars", "type": "TerrestrialPlanet", "mean_radius": "big", "satellites": null}"#,
+                r#"{"name": " ", "type": "TerrestrialPlanet", "mean_radius": 3389.5, "satellites": null}"#,
+                r#"{"name": "Mars", "type": "TerrestrialPlanet", "mean_radius": -1, "satellites": null}"#,
+                r#"{"name": "Venus", "type": "TerrestrialPlanet", "mean_radius": 6051.8, "satellites": null}"#,
+            ],
+        );
+
+        // blank lines are skipped, while the malformed ones don't stop the rest
+        assert_eq!(results.len(), 8);
+        assert_eq!(results[0], Ok(String::from("Mars")));
+        assert!(results[1..7].iter().all(Result::is_err));
+        assert_eq!(results[7], Ok(String::from("Venus")));
+    }
+
+    #[test]
+    fn rejects_malformed_csv_lines() {
+        let results = parse_lines(
+            BulkFormat::Csv,
+            &[
+                "id,name,type,mean_radius,satellites,image",
+                r#",Mars,TerrestrialPlanet,3389.5,"[{""name"":""Phobos"",""first_spacecraft_landing_date"":null}]","#,
+                ",Mars,TerrestrialPlanet,3389.5",
+                ",Mars,TerrestrialPlanet,big,,",
+                ",Mars,DwarfStar,3389.5,,",
+                ",Mars,TerrestrialPlanet,3389.5,[Phobos,",
+                r#",Mars,TerrestrialPlanet,3389.5,,"{""key"": """"}""#,
+                r#",Mars,TerrestrialPlanet,3389.5,"[{""name"":""Phobos""},{""name"":""Phobos""}]","#,
+                ",Venus,TerrestrialPlanet,6051.8,,",
+            ],
+        );
+
+        // the header is read from the first line
+        assert_eq!(results.len(), 8);
+        assert_eq!(results[0], Ok(String::from("Mars")));
+        assert!(results[1..7].iter().all(Result::is_err));
+        assert_eq!(results[7], Ok(String::from("Venus")));
+    }
+}
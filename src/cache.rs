@@ -0,0 +1,60 @@
+mod in_memory;
+mod redis_cache;
+mod single_flight;
+
+use axum::async_trait;
+use futures::stream::BoxStream;
+
+use crate::{
+    error::CustomResult,
+    rate_limit::{RateLimitDecision, RateLimitPolicy},
+};
+
+pub use in_memory::InMemoryCache;
+pub use redis_cache::RedisCache;
+pub use single_flight::SingleFlight;
+
+// message ids are "<milliseconds>-<sequence>", like Redis Stream ids
+#[async_trait]
+pub trait Cache: Send + Sync {
+    async fn get(&self, key: &str) -> CustomResult<Option<Vec<u8>>>;
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl_secs: u64) -> CustomResult<()>;
+
+    async fn delete(&self, key: &str) -> CustomResult<()>;
+
+    // returns `false` without changing anything if the key already exists
+    async fn set_if_absent(&self, key: &str, value: Vec<u8>, ttl_secs: u64) -> CustomResult<bool>;
+
+    // deletes the key only if it still has the value, e.g. a lock which has not expired yet
+    async fn delete_if_equals(&self, key: &str, value: Vec<u8>) -> CustomResult<()>;
+
+    // the TTL is reset on every increment
+    async fn incr(&self, key: &str, ttl_secs: u64) -> CustomResult<u64>;
+
+    // keeps only about `max_len` latest messages of a channel
+    async fn publish(&self, channel: &str, message: String, max_len: usize) -> CustomResult<()>;
+
+    // messages after `last_id` are replayed first, otherwise only new ones are returned
+    async fn subscribe(
+        &self,
+        channel: &str,
+        last_id: Option<String>,
+    ) -> CustomResult<BoxStream<'static, (String, String)>>;
+}
+
+#[async_trait]
+pub trait RateLimiter: Send + Sync {
+    // takes a request of a client identified by the key into account, if it's allowed
+    async fn rate_limit(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> CustomResult<RateLimitDecision>;
+}
+
+fn parse_message_id(id: &str) -> Option<(u64, u64)> {
+    let (ms, seq) = id.split_once('-').unwrap_or((id, "0"));
+
+    Some((ms.parse().ok()?, seq.parse().ok()?))
+}
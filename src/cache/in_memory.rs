@@ -0,0 +1,540 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::async_trait;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use tokio::sync::watch;
+
+use super::{Cache, RateLimiter};
+use crate::{
+    error::CustomResult,
+    rate_limit::{RateLimitAlgorithm, RateLimitDecision, RateLimitPolicy},
+};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Instant,
+}
+
+impl Entry {
+    fn is_expired(&self) -> bool {
+        self.expires_at <= Instant::now()
+    }
+}
+
+// the same algorithms as the Lua scripts of `RedisCache`
+enum RateLimitState {
+    SlidingWindow(VecDeque<Instant>),
+    TokenBucket { tokens: f64, updated_at: Instant },
+}
+
+struct RateLimitEntry {
+    state: RateLimitState,
+    expires_at: Instant,
+}
+
+impl RateLimitEntry {
+    fn new(policy: &RateLimitPolicy, now: Instant) -> Self {
+        let state = match policy.algorithm {
+            RateLimitAlgorithm::SlidingWindow => RateLimitState::SlidingWindow(VecDeque::new()),
+            RateLimitAlgorithm::TokenBucket => RateLimitState::TokenBucket {
+                tokens: policy.limit as f64,
+                updated_at: now,
+            },
+        };
+
+        Self {
+            state,
+            expires_at: now,
+        }
+    }
+
+    fn apply(&mut self, policy: &RateLimitPolicy, now: Instant) -> RateLimitDecision {
+        let period = Duration::from_millis(policy.period_ms());
+
+        match &mut self.state {
+            RateLimitState::SlidingWindow(requests) => {
+                while requests
+                    .front()
+                    .is_some_and(|request| now.duration_since(*request) >= period)
+                {
+                    requests.pop_front();
+                }
+
+                let is_allowed = (requests.len() as u64) < policy.limit;
+                if is_allowed {
+                    requests.push_back(now);
+                }
+
+                let until_expired = |request: Option<&Instant>| {
+                    request.map_or(Duration::ZERO, |r| period - now.duration_since(*r))
+                };
+                let reset_after = until_expired(requests.back());
+                let retry_after = if is_allowed {
+                    Duration::ZERO
+                } else {
+                    until_expired(requests.front())
+                };
+
+                self.expires_at = now + reset_after;
+
+                RateLimitDecision {
+                    is_allowed,
+                    limit: policy.limit,
+                    // the limit may have been lowered since the requests were made
+                    remaining: policy.limit.saturating_sub(requests.len() as u64),
+                    reset_after_ms: reset_after.as_millis() as u64,
+                    retry_after_ms: retry_after.as_millis() as u64,
+                }
+            }
+            RateLimitState::TokenBucket { tokens, updated_at } => {
+                let refill_interval_ms = policy.period_ms() as f64 / policy.limit as f64;
+                let elapsed_ms = now.duration_since(*updated_at).as_millis() as f64;
+
+                *tokens = (*tokens + elapsed_ms / refill_interval_ms).min(policy.limit as f64);
+                *updated_at = now;
+
+                let is_allowed = *tokens >= 1.0;
+                let retry_after_ms = if is_allowed {
+                    *tokens -= 1.0;
+                    0.0
+                } else {
+                    (1.0 - *tokens) * refill_interval_ms
+                };
+                let reset_after_ms = (policy.limit as f64 - *tokens) * refill_interval_ms;
+
+                self.expires_at = now + Duration::from_millis(reset_after_ms.ceil() as u64);
+
+                RateLimitDecision {
+                    is_allowed,
+                    limit: policy.limit,
+                    remaining: tokens.floor() as u64,
+                    reset_after_ms: reset_after_ms.ceil() as u64,
+                    retry_after_ms: retry_after_ms.ceil() as u64,
+                }
+            }
+        }
+    }
+}
+
+// subscribers are woken up by a change of the last message id
+struct Channel {
+    messages: VecDeque<((u64, u64), String)>,
+    last_id: watch::Sender<(u64, u64)>,
+}
+
+impl Channel {
+    fn new() -> Self {
+        Self {
+            messages: VecDeque::new(),
+            last_id: watch::channel((0, 0)).0,
+        }
+    }
+
+    fn get_messages_after(&self, id: (u64, u64)) -> Vec<(String, String)> {
+        self.messages
+            .iter()
+            .filter(|(message_id, _)| *message_id > id)
+            .map(|((ms, seq), message)| (format!("{}-{}", ms, seq), message.clone()))
+            .collect()
+    }
+}
+
+struct State {
+    entries: HashMap<String, Entry>,
+    rate_limits: HashMap<String, RateLimitEntry>,
+    last_purge: Instant,
+}
+
+// a process-local replacement of Redis, which isn't shared between instances
+pub struct InMemoryCache {
+    state: Mutex<State>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>,
+}
+
+impl InMemoryCache {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                entries: HashMap::new(),
+                rate_limits: HashMap::new(),
+                last_purge: Instant::now(),
+            }),
+            channels: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // expired entries are dropped on access, and the rest of them from time to time
+    fn lock_state(&self) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().expect("Cache lock is poisoned");
+
+        if state.last_purge.elapsed() >= PURGE_INTERVAL {
+            state.entries.retain(|_, entry| !entry.is_expired());
+            state
+                .rate_limits
+                .retain(|_, entry| entry.expires_at > Instant::now());
+            state.last_purge = Instant::now();
+        }
+
+        state
+    }
+}
+
+#[async_trait]
+impl Cache for InMemoryCache {
+    async fn get(&self, key: &str) -> CustomResult<Option<Vec<u8>>> {
+        let mut state = self.lock_state();
+
+        match state.entries.get(key) {
+            Some(entry) if entry.is_expired() => {
+                state.entries.remove(key);
+
+                Ok(None)
+            }
+            Some(entry) => Ok(Some(entry.value.clone())),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl_secs: u64) -> CustomResult<()> {
+        let entry = Entry {
+            value,
+            expires_at: Instant::now() + Duration::from_secs(ttl_secs),
+        };
+
+        self.lock_state().entries.insert(key.to_string(), entry);
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> CustomResult<()> {
+        self.lock_state().entries.remove(key);
+
+        Ok(())
+    }
+
+    async fn set_if_absent(&self, key: &str, value: Vec<u8>, ttl_secs: u64) -> CustomResult<bool> {
+        let mut state = self.lock_state();
+
+        if state
+            .entries
+            .get(key)
+            .is_some_and(|entry| !entry.is_expired())
+        {
+            return Ok(false);
+        }
+
+        let entry = Entry {
+            value,
+            expires_at: Instant::now() + Duration::from_secs(ttl_secs),
+        };
+        state.entries.insert(key.to_string(), entry);
+
+        Ok(true)
+    }
+
+    async fn delete_if_equals(&self, key: &str, value: Vec<u8>) -> CustomResult<()> {
+        let mut state = self.lock_state();
+
+        if state
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.value == value)
+        {
+            state.entries.remove(key);
+        }
+
+        Ok(())
+    }
+
+    async fn incr(&self, key: &str, ttl_secs: u64) -> CustomResult<u64> {
+        let mut state = self.lock_state();
+
+        let count = match state.entries.get(key) {
+            Some(entry) if !entry.is_expired() => String::from_utf8_lossy(&entry.value)
+                .parse::<u64>()
+                .unwrap_or_default(),
+            _ => 0,
+        } + 1;
+
+        let entry = Entry {
+            value: count.to_string().into_bytes(),
+            expires_at: Instant::now() + Duration::from_secs(ttl_secs),
+        };
+        state.entries.insert(key.to_string(), entry);
+
+        Ok(count)
+    }
+
+    async fn publish(&self, channel: &str, message: String, max_len: usize) -> CustomResult<()> {
+        let mut channels = self.channels.lock().expect("Cache lock is poisoned");
+        let channel = channels
+            .entry(channel.to_string())
+            .or_insert_with(Channel::new);
+
+        // the same id scheme as Redis Streams: milliseconds and a sequence number within them
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let (last_ms, last_seq) = *channel.last_id.borrow();
+        let id = if now_ms > last_ms {
+            (now_ms, 0)
+        } else {
+            (last_ms, last_seq + 1)
+        };
+
+        channel.messages.push_back((id, message));
+        while channel.messages.len() > max_len {
+            channel.messages.pop_front();
+        }
+
+        channel.last_id.send_replace(id);
+
+        Ok(())
+    }
+
+    async fn subscribe(
+        &self,
+        channel: &str,
+        last_id: Option<String>,
+    ) -> CustomResult<BoxStream<'static, (String, String)>> {
+        let (last_id, receiver) = {
+            let mut channels = self.channels.lock().expect("Cache lock is poisoned");
+            let channel = channels
+                .entry(channel.to_string())
+                .or_insert_with(Channel::new);
+
+            let last_id = last_id
+                .as_deref()
+                .and_then(super::parse_message_id)
+                .unwrap_or_else(|| *channel.last_id.borrow());
+
+            (last_id, channel.last_id.subscribe())
+        };
+
+        let channels = self.channels.clone();
+        let channel = channel.to_string();
+
+        let stream = stream::unfold((receiver, last_id), move |(mut receiver, mut last_id)| {
+            let channels = channels.clone();
+            let channel = channel.clone();
+
+            async move {
+                loop {
+                    // marking the id as seen before reading, so no message is missed
+                    receiver.borrow_and_update();
+
+                    let messages = channels
+                        .lock()
+                        .expect("Cache lock is poisoned")
+                        .get(&channel)
+                        .map(|channel| channel.get_messages_after(last_id))
+                        .unwrap_or_default();
+
+                    if let Some(id) = messages
+                        .last()
+                        .and_then(|(id, _)| super::parse_message_id(id))
+                    {
+                        last_id = id;
+
+                        return Some((messages, (receiver, last_id)));
+                    }
+
+                    receiver.changed().await.ok()?;
+                }
+            }
+        })
+        .flat_map(stream::iter)
+        .boxed();
+
+        Ok(stream)
+    }
+}
+
+#[async_trait]
+impl RateLimiter for InMemoryCache {
+    async fn rate_limit(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> CustomResult<RateLimitDecision> {
+        let now = Instant::now();
+        let mut state = self.lock_state();
+
+        // an expired entry or one of another algorithm starts from scratch
+        let entry = state
+            .rate_limits
+            .entry(key.to_string())
+            .or_insert_with(|| RateLimitEntry::new(policy, now));
+        let is_same_algorithm = matches!(
+            (&entry.state, policy.algorithm),
+            (
+                RateLimitState::SlidingWindow(_),
+                RateLimitAlgorithm::SlidingWindow
+            ) | (
+                RateLimitState::TokenBucket { .. },
+                RateLimitAlgorithm::TokenBucket
+            )
+        );
+        if entry.expires_at <= now || !is_same_algorithm {
+            *entry = RateLimitEntry::new(policy, now);
+        }
+
+        Ok(entry.apply(policy, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_policy(policy: &str) -> RateLimitPolicy {
+        policy.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn expires_entries() {
+        let cache = InMemoryCache::new();
+
+        cache.set("expired", b"value".to_vec(), 0).await.unwrap();
+        cache.set("alive", b"value".to_vec(), 60).await.unwrap();
+        assert_eq!(cache.get("expired").await.unwrap(), None);
+        assert_eq!(cache.get("alive").await.unwrap(), Some(b"value".to_vec()));
+
+        // an expired key is absent for the others as well
+        cache.set("lock", b"old".to_vec(), 0).await.unwrap();
+        assert!(cache
+            .set_if_absent("lock", b"new".to_vec(), 60)
+            .await
+            .unwrap());
+        assert!(!cache
+            .set_if_absent("lock", b"other".to_vec(), 60)
+            .await
+            .unwrap());
+
+        cache
+            .delete_if_equals("lock", b"other".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(cache.get("lock").await.unwrap(), Some(b"new".to_vec()));
+        cache
+            .delete_if_equals("lock", b"new".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(cache.get("lock").await.unwrap(), None);
+
+        assert_eq!(cache.incr("counter", 0).await.unwrap(), 1);
+        assert_eq!(cache.incr("counter", 60).await.unwrap(), 1);
+        assert_eq!(cache.incr("counter", 60).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn limits_requests_by_sliding_window() {
+        let cache = InMemoryCache::new();
+        let policy = get_policy("sliding_window:2/60");
+
+        let decision = cache.rate_limit("client", &policy).await.unwrap();
+        assert!(decision.is_allowed);
+        assert_eq!(decision.remaining, 1);
+
+        let decision = cache.rate_limit("client", &policy).await.unwrap();
+        assert!(decision.is_allowed);
+        assert_eq!(decision.remaining, 0);
+        assert!(decision.reset_after_ms > 59_000 && decision.reset_after_ms <= 60_000);
+
+        let decision = cache.rate_limit("client", &policy).await.unwrap();
+        assert!(!decision.is_allowed);
+        assert_eq!(decision.remaining, 0);
+        assert!(decision.retry_after_ms > 59_000 && decision.retry_after_ms <= 60_000);
+
+        // other clients have limits of their own
+        assert!(cache.rate_limit("other", &policy).await.unwrap().is_allowed);
+
+        // requests made under a higher limit don't make the remaining ones negative
+        let decision = cache
+            .rate_limit("client", &get_policy("sliding_window:1/60"))
+            .await
+            .unwrap();
+        assert!(!decision.is_allowed);
+        assert_eq!(decision.remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn limits_requests_by_token_bucket() {
+        let cache = InMemoryCache::new();
+        let policy = get_policy("token_bucket:2/60");
+
+        let decision = cache.rate_limit("client", &policy).await.unwrap();
+        assert!(decision.is_allowed);
+        assert_eq!(decision.remaining, 1);
+        assert!(decision.reset_after_ms > 29_000 && decision.reset_after_ms <= 30_000);
+
+        let decision = cache.rate_limit("client", &policy).await.unwrap();
+        assert!(decision.is_allowed);
+        assert_eq!(decision.remaining, 0);
+
+        // a token is refilled every 30 seconds
+        let decision = cache.rate_limit("client", &policy).await.unwrap();
+        assert!(!decision.is_allowed);
+        assert_eq!(decision.remaining, 0);
+        assert!(decision.retry_after_ms > 29_000 && decision.retry_after_ms <= 30_000);
+
+        // another algorithm starts from scratch
+        let decision = cache
+            .rate_limit("client", &get_policy("sliding_window:2/60"))
+            .await
+            .unwrap();
+        assert!(decision.is_allowed);
+        assert_eq!(decision.remaining, 1);
+    }
+
+    #[tokio::test]
+    async fn replays_messages_after_id() {
+        let cache = InMemoryCache::new();
+
+        let mut messages = cache.subscribe("channel", None).await.unwrap();
+        for message in ["first", "second", "third"] {
+            cache
+                .publish("channel", message.to_string(), 10)
+                .await
+                .unwrap();
+        }
+
+        let (first_id, first) = messages.next().await.unwrap();
+        assert_eq!(first, "first");
+
+        let replayed = cache
+            .subscribe("channel", Some(first_id.clone()))
+            .await
+            .unwrap()
+            .take(2)
+            .map(|(_, message)| message)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(replayed, ["second", "third"]);
+
+        // only the latest messages are kept
+        cache
+            .publish("channel", String::from("fourth"), 2)
+            .await
+            .unwrap();
+        let replayed = cache
+            .subscribe("channel", Some(first_id))
+            .await
+            .unwrap()
+            .take(2)
+            .map(|(_, message)| message)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(replayed, ["third", "fourth"]);
+    }
+}
@@ -0,0 +1,289 @@
+use axum::async_trait;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use redis::{
+    aio::{Connection, ConnectionManager},
+    streams::{StreamMaxlen, StreamRangeReply, StreamReadOptions, StreamReadReply},
+    AsyncCommands, Client, RedisResult, Script,
+};
+
+use mongodb::bson::oid::ObjectId;
+
+use super::{Cache, RateLimiter};
+use crate::{
+    error::CustomResult,
+    rate_limit::{RateLimitAlgorithm, RateLimitDecision, RateLimitPolicy},
+};
+
+// kept as "event", so planet events published earlier are still readable
+const MESSAGE_FIELD: &str = "event";
+const READ_COUNT: usize = 100;
+const READ_BLOCK_MS: usize = 10_000;
+const DELETE_IF_EQUALS_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+// both scripts take the time from Redis, so instances with different clocks agree,
+// and return {allowed, remaining, reset_after_ms, retry_after_ms}
+const SLIDING_WINDOW_SCRIPT: &str = r#"
+local limit, window = tonumber(ARGV[1]), tonumber(ARGV[2])
+local time = redis.call("TIME")
+local now = time[1] * 1000 + math.floor(time[2] / 1000)
+
+redis.call("ZREMRANGEBYSCORE", KEYS[1], "-inf", now - window)
+local count = redis.call("ZCARD", KEYS[1])
+
+local allowed = 0
+if count < limit then
+    redis.call("ZADD", KEYS[1], now, ARGV[3])
+    redis.call("PEXPIRE", KEYS[1], window)
+    count = count + 1
+    allowed = 1
+end
+
+local oldest = redis.call("ZRANGE", KEYS[1], 0, 0, "WITHSCORES")
+local newest = redis.call("ZRANGE", KEYS[1], -1, -1, "WITHSCORES")
+local reset_after = tonumber(newest[2]) + window - now
+local retry_after = 0
+if allowed == 0 then
+    retry_after = tonumber(oldest[2]) + window - now
+end
+
+-- the limit may have been lowered since the requests were made
+return {allowed, math.max(0, limit - count), reset_after, retry_after}
+"#;
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity, refill_interval = tonumber(ARGV[1]), tonumber(ARGV[2])
+local time = redis.call("TIME")
+local now = time[1] * 1000 + math.floor(time[2] / 1000)
+
+local bucket = redis.call("HMGET", KEYS[1], "tokens", "updated_at")
+local tokens = tonumber(bucket[1]) or capacity
+local updated_at = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + (now - updated_at) / refill_interval)
+
+local allowed = 0
+local retry_after = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+else
+    retry_after = math.ceil((1 - tokens) * refill_interval)
+end
+
+local reset_after = math.ceil((capacity - tokens) * refill_interval)
+redis.call("HSET", KEYS[1], "tokens", tokens, "updated_at", now)
+redis.call("PEXPIRE", KEYS[1], math.max(reset_after, 1))
+
+return {allowed, math.floor(tokens), reset_after, retry_after}
+"#;
+
+pub struct RedisCache {
+    client: Client,
+    connection_manager: ConnectionManager,
+    delete_if_equals_script: Script,
+    sliding_window_script: Script,
+    token_bucket_script: Script,
+}
+
+impl RedisCache {
+    pub async fn new(uri: String) -> RedisResult<Self> {
+        let client = Client::open(uri)?;
+        let connection_manager = client.get_connection_manager().await?;
+
+        Ok(Self {
+            client,
+            connection_manager,
+            delete_if_equals_script: Script::new(DELETE_IF_EQUALS_SCRIPT),
+            sliding_window_script: Script::new(SLIDING_WINDOW_SCRIPT),
+            token_bucket_script: Script::new(TOKEN_BUCKET_SCRIPT),
+        })
+    }
+
+    async fn get_last_message_id(con: &mut Connection, channel: &str) -> CustomResult<String> {
+        let reply: StreamRangeReply = con.xrevrange_count(channel, "+", "-", 1).await?;
+
+        let last_id = reply
+            .ids
+            .into_iter()
+            .next()
+            .map_or_else(|| String::from("0-0"), |stream_id| stream_id.id);
+
+        Ok(last_id)
+    }
+}
+
+#[async_trait]
+impl Cache for RedisCache {
+    async fn get(&self, key: &str) -> CustomResult<Option<Vec<u8>>> {
+        let value = self.connection_manager.clone().get(key).await?;
+
+        Ok(value)
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl_secs: u64) -> CustomResult<()> {
+        let _: () = redis::pipe()
+            .atomic()
+            .set(key, value)
+            .expire(key, ttl_secs as i64)
+            .query_async(&mut self.connection_manager.clone())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> CustomResult<()> {
+        self.connection_manager.clone().del::<_, ()>(key).await?;
+
+        Ok(())
+    }
+
+    async fn set_if_absent(&self, key: &str, value: Vec<u8>, ttl_secs: u64) -> CustomResult<bool> {
+        let reply: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl_secs)
+            .query_async(&mut self.connection_manager.clone())
+            .await?;
+
+        Ok(reply.is_some())
+    }
+
+    // the check and the deletion are atomic only within a script
+    async fn delete_if_equals(&self, key: &str, value: Vec<u8>) -> CustomResult<()> {
+        self.delete_if_equals_script
+            .key(key)
+            .arg(value)
+            .invoke_async::<_, ()>(&mut self.connection_manager.clone())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn incr(&self, key: &str, ttl_secs: u64) -> CustomResult<u64> {
+        let (count, _): (u64, u64) = redis::pipe()
+            .atomic()
+            .incr(key, 1)
+            .expire(key, ttl_secs as i64)
+            .query_async(&mut self.connection_manager.clone())
+            .await?;
+
+        Ok(count)
+    }
+
+    // messages are kept in a Redis Stream, so they can be replayed after reconnecting
+    async fn publish(&self, channel: &str, message: String, max_len: usize) -> CustomResult<()> {
+        self.connection_manager
+            .clone()
+            .xadd_maxlen::<_, _, _, _, ()>(
+                channel,
+                StreamMaxlen::Approx(max_len),
+                "*",
+                &[(MESSAGE_FIELD, message)],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn subscribe(
+        &self,
+        channel: &str,
+        last_id: Option<String>,
+    ) -> CustomResult<BoxStream<'static, (String, String)>> {
+        // blocking reads need their own connection, so the shared manager isn't stalled
+        let mut con = self.client.get_async_connection().await?;
+
+        let last_id = match last_id.filter(|id| super::parse_message_id(id).is_some()) {
+            Some(last_id) => last_id,
+            None => Self::get_last_message_id(&mut con, channel).await?,
+        };
+
+        // the stream owns the connection, so it's closed once a subscriber is dropped
+        let channel = channel.to_string();
+        let stream = stream::unfold(Some((con, last_id)), move |state| {
+            let channel = channel.clone();
+
+            async move {
+                let (mut con, mut last_id) = state?;
+
+                let options = StreamReadOptions::default()
+                    .count(READ_COUNT)
+                    .block(READ_BLOCK_MS);
+
+                let reply: Option<StreamReadReply> =
+                    match con.xread_options(&[&channel], &[&last_id], &options).await {
+                        Ok(reply) => reply,
+                        Err(err) => {
+                            log::error!("Failed to read messages of {}: {}", channel, err);
+                            return Some((Vec::new(), None));
+                        }
+                    };
+
+                let mut messages = Vec::new();
+
+                for stream_id in reply.into_iter().flat_map(|r| r.keys).flat_map(|k| k.ids) {
+                    match stream_id.get::<String>(MESSAGE_FIELD) {
+                        Some(message) => messages.push((stream_id.id.clone(), message)),
+                        None => log::error!("Malformed message of {}: {}", channel, stream_id.id),
+                    }
+                    last_id = stream_id.id;
+                }
+
+                Some((messages, Some((con, last_id))))
+            }
+        })
+        .flat_map(stream::iter)
+        .boxed();
+
+        Ok(stream)
+    }
+}
+
+#[async_trait]
+impl RateLimiter for RedisCache {
+    async fn rate_limit(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> CustomResult<RateLimitDecision> {
+        let invocation = match policy.algorithm {
+            // every request is a member of a sorted set scored by its time
+            RateLimitAlgorithm::SlidingWindow => {
+                let mut invocation = self.sliding_window_script.key(key);
+                invocation
+                    .arg(policy.limit)
+                    .arg(policy.period_ms())
+                    .arg(ObjectId::new().to_hex());
+
+                invocation
+            }
+            RateLimitAlgorithm::TokenBucket => {
+                let mut invocation = self.token_bucket_script.key(key);
+                invocation
+                    .arg(policy.limit)
+                    .arg(policy.period_ms() as f64 / policy.limit as f64);
+
+                invocation
+            }
+        };
+
+        let (allowed, remaining, reset_after_ms, retry_after_ms): (u8, u64, u64, u64) = invocation
+            .invoke_async(&mut self.connection_manager.clone())
+            .await?;
+
+        Ok(RateLimitDecision {
+            is_allowed: allowed == 1,
+            limit: policy.limit,
+            remaining,
+            reset_after_ms,
+            retry_after_ms,
+        })
+    }
+}
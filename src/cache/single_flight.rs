@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use mongodb::bson::oid::ObjectId;
+use tokio::sync::Mutex as AsyncMutex;
+
+use super::Cache;
+use crate::error::CustomResult;
+
+const LOCK_KEY_SUFFIX: &str = ":lock";
+const STALE_KEY_SUFFIX: &str = ":stale";
+// a value is expected to be loaded by then, otherwise the lock is considered abandoned
+const LOCK_TTL_SECS: u64 = 5;
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// makes only one request load an expired value: concurrent requests of the same process
+// wait for it, and other instances are coordinated by a short lock in the cache
+pub struct SingleFlight {
+    cache: Arc<dyn Cache>,
+    in_flight: Mutex<HashMap<String, Weak<AsyncMutex<()>>>>,
+    // the last value is kept that long after expiration, and served while it's being reloaded
+    stale_ttl_secs: Option<u64>,
+}
+
+impl SingleFlight {
+    pub fn new(cache: Arc<dyn Cache>, stale_ttl_secs: Option<u64>) -> Self {
+        Self {
+            cache,
+            in_flight: Mutex::new(HashMap::new()),
+            stale_ttl_secs,
+        }
+    }
+
+    pub async fn get_or_load<F, Fut>(
+        &self,
+        key: &str,
+        ttl_secs: u64,
+        load: F,
+    ) -> CustomResult<Vec<u8>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = CustomResult<Vec<u8>>>,
+    {
+        if let Some(value) = self.cache.get(key).await? {
+            return Ok(value);
+        }
+
+        let stale_value = match self.stale_ttl_secs {
+            Some(_) => self.cache.get(&get_stale_key(key)).await?,
+            None => None,
+        };
+
+        // the stale value is served instead of waiting for the one which is being loaded
+        let in_flight = self.get_in_flight(key);
+        let _guard = match in_flight.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => match stale_value {
+                Some(stale_value) => return Ok(stale_value),
+                None => in_flight.lock_owned().await,
+            },
+        };
+
+        // the value may have been loaded while waiting for the lock
+        if let Some(value) = self.cache.get(key).await? {
+            return Ok(value);
+        }
+
+        let lock_key = format!("{}{}", key, LOCK_KEY_SUFFIX);
+        let lock_token = ObjectId::new().bytes().to_vec();
+        let is_locked = self
+            .cache
+            .set_if_absent(&lock_key, lock_token.clone(), LOCK_TTL_SECS)
+            .await?;
+
+        if !is_locked {
+            if let Some(stale_value) = stale_value {
+                return Ok(stale_value);
+            }
+
+            if let Some(value) = self.wait_for_other_instance(key).await? {
+                return Ok(value);
+            }
+
+            log::warn!(
+                "Value of {} hasn't been loaded in time, loading it anyway",
+                key
+            );
+        }
+
+        let result = match load().await {
+            Ok(value) => self.set(key, value.clone(), ttl_secs).await.map(|_| value),
+            Err(err) => Err(err),
+        };
+
+        if is_locked {
+            self.cache.delete_if_equals(&lock_key, lock_token).await?;
+        }
+
+        result
+    }
+
+    // the stale value is removed as well, since it's no longer valid
+    pub async fn invalidate(&self, key: &str) -> CustomResult<()> {
+        self.cache.delete(key).await?;
+
+        if self.stale_ttl_secs.is_some() {
+            self.cache.delete(&get_stale_key(key)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl_secs: u64) -> CustomResult<()> {
+        if let Some(stale_ttl_secs) = self.stale_ttl_secs {
+            self.cache
+                .set(
+                    &get_stale_key(key),
+                    value.clone(),
+                    ttl_secs + stale_ttl_secs,
+                )
+                .await?;
+        }
+
+        self.cache.set(key, value, ttl_secs).await
+    }
+
+    fn get_in_flight(&self, key: &str) -> Arc<AsyncMutex<()>> {
+        let mut in_flight = self.in_flight.lock().expect("In-flight lock is poisoned");
+
+        // keys are released along with the last request waiting for them
+        in_flight.retain(|_, mutex| mutex.strong_count() > 0);
+
+        if let Some(mutex) = in_flight.get(key).and_then(Weak::upgrade) {
+            return mutex;
+        }
+
+        let mutex = Arc::new(AsyncMutex::new(()));
+        in_flight.insert(key.to_string(), Arc::downgrade(&mutex));
+
+        mutex
+    }
+
+    async fn wait_for_other_instance(&self, key: &str) -> CustomResult<Option<Vec<u8>>> {
+        let attempts =
+            Duration::from_secs(LOCK_TTL_SECS).as_millis() / LOCK_POLL_INTERVAL.as_millis();
+
+        for _ in 0..attempts {
+            tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+
+            if let Some(value) = self.cache.get(key).await? {
+                return Ok(Some(value));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn get_stale_key(key: &str) -> String {
+    format!("{}{}", key, STALE_KEY_SUFFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::future;
+
+    use super::*;
+    use crate::cache::InMemoryCache;
+
+    // every load takes a while, so the concurrent ones overlap with it
+    async fn load_all(
+        single_flight: &SingleFlight,
+        loads: &AtomicUsize,
+        count: usize,
+    ) -> Vec<Vec<u8>> {
+        let requests = (0..count).map(|_| {
+            single_flight.get_or_load("key", 60, || async {
+                let load = loads.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+
+                Ok(format!("value {}", load).into_bytes())
+            })
+        });
+
+        future::join_all(requests)
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn collapses_concurrent_loads() {
+        let single_flight = SingleFlight::new(Arc::new(InMemoryCache::new()), None);
+        let loads = AtomicUsize::new(0);
+
+        let values = load_all(&single_flight, &loads, 10).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert!(values.iter().all(|value| value == b"value 0"));
+
+        // a loaded value is served from the cache
+        load_all(&single_flight, &loads, 10).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        single_flight.invalidate("key").await.unwrap();
+        let values = load_all(&single_flight, &loads, 10).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+        assert!(values.iter().all(|value| value == b"value 1"));
+    }
+
+    #[tokio::test]
+    async fn serves_stale_value_while_loading() {
+        let cache = Arc::new(InMemoryCache::new());
+        let single_flight = SingleFlight::new(cache.clone(), Some(60));
+        let loads = AtomicUsize::new(0);
+
+        load_all(&single_flight, &loads, 1).await;
+        cache.delete("key").await.unwrap();
+
+        // only the request which reloads the value waits for it
+        let values = load_all(&single_flight, &loads, 10).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+        assert_eq!(values[0], b"value 1");
+        assert!(values[1..].iter().all(|value| value == b"value 0"));
+    }
+}
@@ -0,0 +1,440 @@
+use std::{
+    convert::Infallible,
+    mem,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header, HeaderMap},
+    response::Response,
+};
+use futures::future::BoxFuture;
+use tower::{Layer, Service};
+
+const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+const X_REAL_IP: &str = "X-Real-IP";
+
+// the address of a client as seen behind trusted proxies, it's added to extensions of a request
+#[derive(Copy, Clone, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+// a header which trusted proxies write the address of their peer to
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ForwardedHeader {
+    Forwarded,
+    XForwardedFor,
+    XRealIp,
+}
+
+impl ForwardedHeader {
+    fn name(&self) -> &'static str {
+        match self {
+            ForwardedHeader::Forwarded => header::FORWARDED.as_str(),
+            ForwardedHeader::XForwardedFor => X_FORWARDED_FOR,
+            ForwardedHeader::XRealIp => X_REAL_IP,
+        }
+    }
+}
+
+impl FromStr for ForwardedHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            ForwardedHeader::Forwarded,
+            ForwardedHeader::XForwardedFor,
+            ForwardedHeader::XRealIp,
+        ]
+        .into_iter()
+        .find(|header| header.name().eq_ignore_ascii_case(s.trim()))
+        .ok_or_else(|| format!("Unknown forwarded header: {}", s))
+    }
+}
+
+// a network in CIDR notation, e.g. "10.0.0.0/8", a single address is a network of its own
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, to_canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let get_malformed = || format!("Invalid IP network: {}", s);
+
+        let (address, prefix_len) = match s.trim().split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s.trim(), None),
+        };
+
+        let address = to_canonical(address.parse().map_err(|_| get_malformed())?);
+        let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .ok_or_else(get_malformed)?,
+            None => max_prefix_len,
+        };
+
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+// only the header which trusted proxies write is taken into account, otherwise a client
+// could pretend to be another one by sending a header which proxies pass through untouched
+#[derive(Clone, Debug)]
+pub struct ClientIpResolver {
+    header: ForwardedHeader,
+    trusted_proxies: Vec<IpNetwork>,
+}
+
+impl ClientIpResolver {
+    pub fn new(header: ForwardedHeader) -> Self {
+        Self {
+            header,
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    // e.g. "10.0.0.0/8,192.168.0.1"
+    pub fn with_trusted_proxies(mut self, trusted_proxies: &str) -> Result<Self, String> {
+        for trusted_proxy in trusted_proxies.split(',').filter(|p| !p.trim().is_empty()) {
+            self.trusted_proxies.push(trusted_proxy.parse()?);
+        }
+
+        Ok(self)
+    }
+
+    // proxies append the address of their peer, so the chain is walked from the closest hop
+    // until an untrusted one, which is the client; an unknown or obfuscated hop stops the walk
+    pub fn resolve(&self, peer_addr: &SocketAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client_ip = to_canonical(peer_addr.ip());
+
+        if !self.is_trusted(client_ip) {
+            return client_ip;
+        }
+
+        for hop in self.get_forwarded_chain(headers).iter().rev() {
+            let Some(hop) = hop else {
+                break;
+            };
+
+            client_ip = *hop;
+
+            if !self.is_trusted(client_ip) {
+                break;
+            }
+        }
+
+        client_ip
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies
+            .iter()
+            .any(|trusted_proxy| trusted_proxy.contains(ip))
+    }
+
+    // an address which can't be parsed is `None`
+    fn get_forwarded_chain(&self, headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+        let nodes = headers
+            .get_all(self.header.name())
+            .iter()
+            .map(|value| value.to_str().unwrap_or_default())
+            .flat_map(|value| value.split(','));
+
+        match self.header {
+            // e.g. `for=192.0.2.43;proto=https, for="[2001:db8:cafe::17]:4711"`
+            ForwardedHeader::Forwarded => nodes
+                .filter_map(|element| {
+                    element.split(';').find_map(|pair| {
+                        let (name, value) = pair.split_once('=')?;
+
+                        name.trim()
+                            .eq_ignore_ascii_case("for")
+                            .then(|| parse_node(value))
+                    })
+                })
+                .collect(),
+            ForwardedHeader::XForwardedFor | ForwardedHeader::XRealIp => {
+                nodes.map(parse_node).collect()
+            }
+        }
+    }
+}
+
+// resolves the address of a client for every request, so rate limiting and logging
+// see the client rather than a proxy
+#[derive(Clone)]
+pub struct ClientIpLayer {
+    client_ip_resolver: Arc<ClientIpResolver>,
+}
+
+impl ClientIpLayer {
+    pub fn new(client_ip_resolver: ClientIpResolver) -> Self {
+        Self {
+            client_ip_resolver: Arc::new(client_ip_resolver),
+        }
+    }
+}
+
+impl<S> Layer<S> for ClientIpLayer {
+    type Service = ClientIpMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClientIpMiddleware {
+            inner,
+            client_ip_resolver: self.client_ip_resolver.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClientIpMiddleware<S> {
+    inner: S,
+    client_ip_resolver: Arc<ClientIpResolver>,
+}
+
+impl<S> Service<Request> for ClientIpMiddleware<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        // the service which has been polled ready is taken, and its clone is left instead
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+
+        if let Some(&ConnectInfo(addr)) = request.extensions().get::<ConnectInfo<SocketAddr>>() {
+            let client_ip = self.client_ip_resolver.resolve(&addr, request.headers());
+
+            if client_ip == to_canonical(addr.ip()) {
+                log::debug!("{} {} from {}", request.method(), request.uri(), client_ip);
+            } else {
+                log::debug!(
+                    "{} {} from {} forwarded by {}",
+                    request.method(),
+                    request.uri(),
+                    client_ip,
+                    addr
+                );
+            }
+
+            request.extensions_mut().insert(ClientIp(client_ip));
+        }
+
+        Box::pin(async move { inner.call(request).await })
+    }
+}
+
+// e.g. 192.0.2.43, "192.0.2.43:4711", "[2001:db8:cafe::17]:4711" or 2001:db8:cafe::17
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    let ip = match node.strip_prefix('[') {
+        Some(node) => node.split_once(']')?.0.parse().ok()?,
+        None => node
+            .parse::<IpAddr>()
+            .ok()
+            .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))?,
+    };
+
+    Some(to_canonical(ip))
+}
+
+// IPv4 clients of a dual-stack listener are seen as IPv4-mapped IPv6 addresses
+fn to_canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ipv6) => ipv6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_resolver(header: ForwardedHeader) -> ClientIpResolver {
+        ClientIpResolver::new(header)
+            .with_trusted_proxies("10.0.0.0/8, 192.168.1.1, fd00::/8")
+            .unwrap()
+    }
+
+    fn get_headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    fn resolve(resolver: &ClientIpResolver, peer_addr: &str, headers: &HeaderMap) -> String {
+        resolver
+            .resolve(&peer_addr.parse().unwrap(), headers)
+            .to_string()
+    }
+
+    #[test]
+    fn matches_ip_networks() {
+        let network = "10.0.0.0/8".parse::<IpNetwork>().unwrap();
+        assert!(network.contains("10.0.0.1".parse().unwrap()));
+        assert!(network.contains("10.255.255.255".parse().unwrap()));
+        assert!(!network.contains("11.0.0.1".parse().unwrap()));
+        assert!(network.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!network.contains("::1".parse().unwrap()));
+
+        let network = "192.168.1.1".parse::<IpNetwork>().unwrap();
+        assert!(network.contains("192.168.1.1".parse().unwrap()));
+        assert!(!network.contains("192.168.1.2".parse().unwrap()));
+
+        let network = "fd00::/8".parse::<IpNetwork>().unwrap();
+        assert!(network.contains("fd12:3456::1".parse().unwrap()));
+        assert!(!network.contains("fe80::1".parse().unwrap()));
+
+        let network = "0.0.0.0/0".parse::<IpNetwork>().unwrap();
+        assert!(network.contains("203.0.113.7".parse().unwrap()));
+        assert!(!network.contains("2001:db8::1".parse().unwrap()));
+
+        for network in ["10.0.0.0/33", "fd00::/129", "10.0.0.0/x", "localhost", ""] {
+            assert!(network.parse::<IpNetwork>().is_err(), "{}", network);
+        }
+    }
+
+    #[test]
+    fn ignores_headers_of_untrusted_peers() {
+        let resolver = get_resolver(ForwardedHeader::XForwardedFor);
+        let headers = get_headers(&[(X_FORWARDED_FOR, "203.0.113.7")]);
+
+        assert_eq!(
+            resolve(&resolver, "198.51.100.1:1234", &headers),
+            "198.51.100.1"
+        );
+    }
+
+    #[test]
+    fn walks_chain_from_closest_hop() {
+        let resolver = get_resolver(ForwardedHeader::XForwardedFor);
+
+        // the leftmost address is set by the client, so it can't be trusted
+        let headers = get_headers(&[(X_FORWARDED_FOR, "1.1.1.1, 203.0.113.7, 10.0.0.2")]);
+        assert_eq!(resolve(&resolver, "10.0.0.1:1234", &headers), "203.0.113.7");
+
+        let headers = get_headers(&[
+            (X_FORWARDED_FOR, "203.0.113.7"),
+            (X_FORWARDED_FOR, "192.168.1.1"),
+        ]);
+        assert_eq!(resolve(&resolver, "10.0.0.1:1234", &headers), "203.0.113.7");
+
+        // only trusted proxies are in the chain, so the farthest one is the client
+        let headers = get_headers(&[(X_FORWARDED_FOR, "10.0.0.3, 10.0.0.2")]);
+        assert_eq!(resolve(&resolver, "10.0.0.1:1234", &headers), "10.0.0.3");
+
+        let headers = get_headers(&[(X_FORWARDED_FOR, "203.0.113.7, unknown, 10.0.0.2")]);
+        assert_eq!(resolve(&resolver, "10.0.0.1:1234", &headers), "10.0.0.2");
+
+        let headers = get_headers(&[]);
+        assert_eq!(resolve(&resolver, "10.0.0.1:1234", &headers), "10.0.0.1");
+
+        let headers = get_headers(&[(X_FORWARDED_FOR, "203.0.113.7")]);
+        assert_eq!(
+            resolve(&resolver, "[::ffff:10.0.0.1]:1234", &headers),
+            "203.0.113.7"
+        );
+    }
+
+    #[test]
+    fn reads_only_configured_header() {
+        let headers = get_headers(&[
+            ("Forwarded", "for=1.1.1.1"),
+            (X_FORWARDED_FOR, "203.0.113.7"),
+            (X_REAL_IP, "2.2.2.2"),
+        ]);
+
+        let resolver = get_resolver(ForwardedHeader::XForwardedFor);
+        assert_eq!(resolve(&resolver, "10.0.0.1:1234", &headers), "203.0.113.7");
+
+        let resolver = get_resolver(ForwardedHeader::XRealIp);
+        assert_eq!(resolve(&resolver, "10.0.0.1:1234", &headers), "2.2.2.2");
+
+        let resolver = get_resolver(ForwardedHeader::Forwarded);
+        assert_eq!(resolve(&resolver, "10.0.0.1:1234", &headers), "1.1.1.1");
+    }
+
+    #[test]
+    fn parses_forwarded_nodes() {
+        let resolver = get_resolver(ForwardedHeader::Forwarded);
+
+        let headers = get_headers(&[(
+            "Forwarded",
+            "for=203.0.113.7;proto=https, For=\"[2001:db8:cafe::17]:4711\"",
+        )]);
+        assert_eq!(
+            resolve(&resolver, "10.0.0.1:1234", &headers),
+            "2001:db8:cafe::17"
+        );
+
+        let headers = get_headers(&[("Forwarded", "for=\"203.0.113.7:8080\";by=10.0.0.1")]);
+        assert_eq!(resolve(&resolver, "10.0.0.1:1234", &headers), "203.0.113.7");
+
+        let headers = get_headers(&[("Forwarded", "for=_hidden, for=10.0.0.2")]);
+        assert_eq!(resolve(&resolver, "10.0.0.1:1234", &headers), "10.0.0.2");
+    }
+
+    #[test]
+    fn parses_forwarded_header_names() {
+        assert_eq!(
+            "x-forwarded-for".parse::<ForwardedHeader>(),
+            Ok(ForwardedHeader::XForwardedFor)
+        );
+        assert_eq!(
+            "X-Real-IP".parse::<ForwardedHeader>(),
+            Ok(ForwardedHeader::XRealIp)
+        );
+        assert_eq!(
+            "forwarded".parse::<ForwardedHeader>(),
+            Ok(ForwardedHeader::Forwarded)
+        );
+        assert!("via".parse::<ForwardedHeader>().is_err());
+    }
+}
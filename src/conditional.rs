@@ -0,0 +1,190 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::CustomResult;
+
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+impl Validators {
+    pub fn headers(&self, cache_control: &'static str) -> CustomResult<HeaderMap> {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(header::ETAG, HeaderValue::from_str(&self.etag)?);
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(cache_control),
+        );
+
+        if let Some(last_modified) = self.last_modified {
+            let last_modified = last_modified.format(HTTP_DATE_FORMAT).to_string();
+            headers.insert(
+                header::LAST_MODIFIED,
+                HeaderValue::from_str(&last_modified)?,
+            );
+        }
+
+        Ok(headers)
+    }
+
+    // If-Modified-Since is ignored when If-None-Match is present (RFC 9110, 13.1.3)
+    pub fn is_not_modified(&self, request_headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = request_headers.get(header::IF_NONE_MATCH) {
+            return if_none_match.to_str().is_ok_and(|if_none_match| {
+                if_none_match.trim() == "*"
+                    || parse_etags(if_none_match)
+                        .iter()
+                        .any(|etag| weak_eq(etag, &self.etag))
+            });
+        }
+
+        let if_modified_since = request_headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok());
+
+        match (self.last_modified, if_modified_since) {
+            (Some(last_modified), Some(if_modified_since)) => {
+                last_modified.timestamp() <= if_modified_since.timestamp()
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn get_strong_etag(content: &[u8]) -> String {
+    format_etag(&Sha256::digest(content))
+}
+
+pub fn get_json_etag<T: Serialize>(value: &T) -> CustomResult<String> {
+    Ok(get_strong_etag(&serde_json::to_vec(value)?))
+}
+
+pub fn format_etag(hash: &[u8]) -> String {
+    let hash = hash
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    format!("\"{}\"", hash)
+}
+
+// `None` means there is no precondition, an empty list can never be matched
+pub fn get_if_match(headers: &HeaderMap) -> Option<Vec<String>> {
+    let if_match = headers.get(header::IF_MATCH)?;
+
+    let Ok(if_match) = if_match.to_str() else {
+        return Some(Vec::new());
+    };
+
+    if if_match.trim() == "*" {
+        return None;
+    }
+
+    // If-Match uses the strong comparison, so weak tags never match
+    let etags = parse_etags(if_match)
+        .into_iter()
+        .filter(|etag| !etag.starts_with("W/"))
+        .collect();
+
+    Some(etags)
+}
+
+fn parse_etags(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|etag| etag.trim().to_string())
+        .filter(|etag| !etag.is_empty())
+        .collect()
+}
+
+fn weak_eq(left: &str, right: &str) -> bool {
+    left.trim_start_matches("W/") == right.trim_start_matches("W/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_headers(headers: &[(header::HeaderName, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    fn get_validators(etag: &str) -> Validators {
+        Validators {
+            etag: etag.to_string(),
+            last_modified: DateTime::from_timestamp(1_700_000_000, 0),
+        }
+    }
+
+    #[test]
+    fn matches_if_none_match() {
+        let validators = get_validators("\"abc\"");
+
+        for (if_none_match, is_not_modified) in [
+            ("*", true),
+            (" * ", true),
+            ("\"abc\"", true),
+            ("W/\"abc\"", true),
+            ("\"xyz\", \"abc\"", true),
+            ("\"xyz\",W/\"abc\"", true),
+            ("\"xyz\"", false),
+            ("\"xyz\", \"uvw\"", false),
+            ("", false),
+        ] {
+            let headers = get_headers(&[(header::IF_NONE_MATCH, if_none_match)]);
+            assert_eq!(
+                validators.is_not_modified(&headers),
+                is_not_modified,
+                "{}",
+                if_none_match
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_if_modified_since_along_with_if_none_match() {
+        let validators = get_validators("\"abc\"");
+        let if_modified_since = "Wed, 15 Nov 2023 00:00:00 GMT";
+
+        let headers = get_headers(&[(header::IF_MODIFIED_SINCE, if_modified_since)]);
+        assert!(validators.is_not_modified(&headers));
+
+        let headers = get_headers(&[
+            (header::IF_NONE_MATCH, "\"xyz\""),
+            (header::IF_MODIFIED_SINCE, if_modified_since),
+        ]);
+        assert!(!validators.is_not_modified(&headers));
+
+        let headers = get_headers(&[(header::IF_MODIFIED_SINCE, "Mon, 13 Nov 2023 00:00:00 GMT")]);
+        assert!(!validators.is_not_modified(&headers));
+    }
+
+    #[test]
+    fn parses_if_match() {
+        assert_eq!(get_if_match(&HeaderMap::new()), None);
+        assert_eq!(get_if_match(&get_headers(&[(header::IF_MATCH, "*")])), None);
+
+        // weak tags never match, so only the strong ones are left
+        assert_eq!(
+            get_if_match(&get_headers(&[(
+                header::IF_MATCH,
+                "\"abc\", W/\"xyz\",\"uvw\""
+            )])),
+            Some(vec![String::from("\"abc\""), String::from("\"uvw\"")])
+        );
+        assert_eq!(
+            get_if_match(&get_headers(&[(header::IF_MATCH, "W/\"abc\"")])),
+            Some(Vec::new())
+        );
+    }
+}
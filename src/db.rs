@@ -1,124 +1,131 @@
-use mongodb::{
-    bson::{doc, oid::ObjectId, Document},
-    error::Result,
-    Client, Collection,
-};
+mod in_memory;
+mod mongo_db;
+
+use axum::async_trait;
+use futures::stream::BoxStream;
+use mongodb::bson::{oid::ObjectId, Document};
 use rust_embed::RustEmbed;
-use tokio_stream::StreamExt;
 
 use crate::{
+    conditional,
     error::{CustomError, CustomResult},
-    model::{Planet, PlanetType, User},
+    model::{Planet, PlanetImage, PlanetImageInfo, PlanetSatellite, Satellite, User},
+    query::{Page, PageRequest, PlanetFilter, SatelliteFilter},
 };
 
-const DB_NAME: &str = "solar_system_info";
-const PLANETS_COLLECTION_NAME: &str = "planets";
-const USERS_COLLECTION_NAME: &str = "users";
+pub use in_memory::InMemoryDb;
+pub use mongo_db::MongoDbClient;
 
-#[derive(Clone)]
-pub struct MongoDbClient {
-    client: Client,
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn get_user(&self, username: String) -> CustomResult<User>;
 }
 
-impl MongoDbClient {
-    pub async fn new(uri: String) -> Result<Self> {
-        Ok(Self {
-            client: Client::with_uri_str(uri).await?,
-        })
-    }
-
-    fn get_planets_collection(&self) -> Collection<Planet> {
-        self.client
-            .database(DB_NAME)
-            .collection(PLANETS_COLLECTION_NAME)
-    }
-
-    fn get_users_collection(&self) -> Collection<User> {
-        self.client
-            .database(DB_NAME)
-            .collection(USERS_COLLECTION_NAME)
-    }
-
-    pub async fn get_user(&self, username: String) -> CustomResult<User> {
-        let filter = doc! { "username": username.clone() };
-
-        self.get_users_collection()
-            .find_one(filter, None)
-            .await?
-            .ok_or(CustomError::UserNotFound {
-                message: format!("Can't find a user by username: {}", username),
-            })
-    }
-
-    pub async fn get_planets(&self, planet_type: Option<PlanetType>) -> CustomResult<Vec<Planet>> {
-        let filter = planet_type.map(|pt| doc! { "type": pt.to_string() });
-        let mut planets = self.get_planets_collection().find(filter, None).await?;
-
-        let mut result = Vec::new();
-
-        while let Some(planet) = planets.next().await {
-            result.push(planet?);
-        }
-
-        Ok(result)
-    }
+// writes take the revision a planet is expected to have, and fail with
+// `CustomError::PreconditionFailed` if it has been changed in the meantime
+#[async_trait]
+pub trait PlanetRepository: Send + Sync {
+    async fn get_planets(
+        &self,
+        planet_filter: &PlanetFilter,
+        page_request: &PageRequest,
+    ) -> CustomResult<Page<Planet>>;
+
+    async fn get_all_planets(&self) -> CustomResult<BoxStream<'static, CustomResult<Planet>>>;
+
+    // returns the upserted planet along with the one it has replaced, if any
+    async fn upsert_planet_by_name(&self, planet: Planet)
+        -> CustomResult<(Planet, Option<Planet>)>;
+
+    async fn get_planet(&self, planet_id: ObjectId) -> CustomResult<Planet>;
+
+    async fn create_planet(&self, planet: Planet) -> CustomResult<Planet>;
+
+    async fn delete_planet(&self, planet_id: ObjectId, revision: i64) -> CustomResult<Planet>;
+
+    async fn update_planet(
+        &self,
+        planet_id: ObjectId,
+        planet: Planet,
+        revision: i64,
+    ) -> CustomResult<Planet>;
+
+    async fn patch_planet(
+        &self,
+        planet_id: ObjectId,
+        set: Document,
+        unset: Document,
+        revision: i64,
+    ) -> CustomResult<Planet>;
+
+    async fn get_all_satellites(
+        &self,
+        satellite_filter: &SatelliteFilter,
+    ) -> CustomResult<Vec<PlanetSatellite>>;
+
+    async fn create_satellite(
+        &self,
+        planet_id: ObjectId,
+        satellite: Satellite,
+        revision: i64,
+    ) -> CustomResult<Planet>;
+
+    async fn update_satellite(
+        &self,
+        planet_id: ObjectId,
+        satellite_name: &str,
+        satellite: Satellite,
+        revision: i64,
+    ) -> CustomResult<Planet>;
+
+    async fn delete_satellite(
+        &self,
+        planet_id: ObjectId,
+        satellite_name: &str,
+        revision: i64,
+    ) -> CustomResult<Planet>;
+}
 
-    pub async fn get_planet(&self, planet_id: ObjectId) -> CustomResult<Planet> {
-        let filter = doc! { "_id": &planet_id };
+// uploaded images of planets, the embedded ones are used if there are none
+#[async_trait]
+pub trait ImageRepository: Send + Sync {
+    async fn get_planet_image_info(
+        &self,
+        planet_id: ObjectId,
+    ) -> CustomResult<Option<PlanetImageInfo>>;
 
-        self.get_planets_collection()
-            .find_one(filter, None)
-            .await?
-            .ok_or(CustomError::NotFound {
-                message: format!("Can't find a planet by id: {}", planet_id),
-            })
-    }
+    async fn get_planet_image(&self, planet_id: ObjectId) -> CustomResult<Option<Vec<u8>>>;
 
-    pub async fn create_planet(&self, planet: Planet) -> CustomResult<Planet> {
-        let collection = self.get_planets_collection();
+    async fn put_planet_image(
+        &self,
+        planet_id: ObjectId,
+        image_info: PlanetImageInfo,
+        image: Vec<u8>,
+    ) -> CustomResult<()>;
 
-        let insert_result = collection.insert_one(planet, None).await?;
+    async fn delete_planet_image(&self, planet_id: ObjectId) -> CustomResult<()>;
+}
 
-        let filter = doc! { "_id": &insert_result.inserted_id };
-        collection
-            .find_one(filter, None)
-            .await?
-            .ok_or(CustomError::NotFound {
-                message: String::from("Can't find created planet"),
-            })
+fn get_precondition_failed(planet: &Planet, revision: i64) -> CustomError {
+    CustomError::PreconditionFailed {
+        message: format!(
+            "Planet {} has been modified: expected revision {}, but got {}",
+            planet.id.map(|id| id.to_string()).unwrap_or_default(),
+            revision,
+            planet.revision
+        ),
     }
+}
 
-    pub async fn delete_planet(&self, planet_id: ObjectId) -> CustomResult<()> {
-        let collection = self.get_planets_collection();
-
-        let filter = doc! { "_id": &planet_id };
-
-        collection
-            .find_one_and_delete(filter, None)
-            .await?
-            .ok_or(CustomError::NotFound {
-                message: format!("Can't find planet by id: {}", planet_id),
-            })?;
-
-        Ok(())
+fn get_satellite_not_found(satellite_name: &str) -> CustomError {
+    CustomError::NotFound {
+        message: format!("Can't find a satellite by name: {}", satellite_name),
     }
+}
 
-    pub async fn update_planet(&self, planet_id: ObjectId, planet: Planet) -> CustomResult<Planet> {
-        let collection = self.get_planets_collection();
-
-        let query = doc! { "_id": &planet_id };
-        let update = doc! { "&set": Document::from(&planet) };
-        let _ = collection.update_one(query, update, None).await?;
-
-        let filter = doc! { "_id": &planet_id };
-        let planet = collection
-            .find_one(filter, None)
-            .await?
-            .ok_or(CustomError::NotFound {
-                message: format!("Can't find updated planet: {}", planet_id),
-            })?;
-
-        Ok(planet)
+fn get_satellite_conflict(satellite_name: &str) -> CustomError {
+    CustomError::Conflict {
+        message: format!("Satellite already exists: {}", satellite_name),
     }
 }
 
@@ -126,10 +133,19 @@ impl MongoDbClient {
 #[folder = "images"]
 struct Asset;
 
-pub fn get_planet_image(planet_name: &str) -> Vec<u8> {
-    let filename = format!("{}.jpg", planet_name.to_lowercase());
-
-    let image = Asset::get(&filename).expect("Failed to open image");
+pub fn get_planet_image(planet_image: &PlanetImage) -> Option<Vec<u8>> {
+    Asset::get(&planet_image.key).map(|image| image.data.to_vec())
+}
 
-    image.data.to_vec()
+pub fn get_planet_image_info(planet_image: &PlanetImage) -> Option<PlanetImageInfo> {
+    let image = Asset::get(&planet_image.key)?;
+
+    Some(PlanetImageInfo {
+        content_type: planet_image.content_type.clone(),
+        etag: conditional::format_etag(&image.metadata.sha256_hash()),
+        uploaded_at: image
+            .metadata
+            .last_modified()
+            .map(|secs| mongodb::bson::DateTime::from_millis(secs as i64 * 1000)),
+    })
 }
@@ -0,0 +1,796 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, MutexGuard},
+};
+
+use axum::async_trait;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use mongodb::bson::{self, oid::ObjectId, Bson, DateTime, Document};
+use serde_json::Value as JsonValue;
+
+use super::{ImageRepository, PlanetRepository, UserRepository};
+use crate::{
+    error::{CustomError, CustomResult},
+    model::{self, Planet, PlanetImageInfo, PlanetSatellite, Satellite, User},
+    query::{Page, PageCursor, PageRequest, PlanetFilter, PlanetSort, SatelliteFilter, SortValue},
+};
+
+const PLANETS_SEED: &str = include_str!("../../mongodb-init/collections/planets.json");
+const USERS_SEED: &str = include_str!("../../mongodb-init/collections/users.json");
+
+// keeps everything in process memory, seeded with the same data as MongoDB
+pub struct InMemoryDb {
+    planets: Mutex<BTreeMap<ObjectId, Planet>>,
+    images: Mutex<HashMap<ObjectId, (PlanetImageInfo, Vec<u8>)>>,
+    users: HashMap<String, User>,
+}
+
+impl InMemoryDb {
+    pub fn new() -> Self {
+        let planets = parse_seed::<Planet>(PLANETS_SEED)
+            .into_iter()
+            .map(|(_, mut planet)| {
+                let id = ObjectId::new();
+                planet.id = Some(id);
+
+                (id, planet)
+            })
+            .collect();
+
+        let users = parse_seed::<User>(USERS_SEED)
+            .into_iter()
+            .filter_map(|(document, user)| {
+                let username = document.get_str("username").ok()?.to_string();
+
+                Some((username, user))
+            })
+            .collect();
+
+        Self {
+            planets: Mutex::new(planets),
+            images: Mutex::new(HashMap::new()),
+            users,
+        }
+    }
+
+    fn lock_planets(&self) -> MutexGuard<'_, BTreeMap<ObjectId, Planet>> {
+        self.planets.lock().expect("Planets lock is poisoned")
+    }
+
+    fn lock_images(&self) -> MutexGuard<'_, HashMap<ObjectId, (PlanetImageInfo, Vec<u8>)>> {
+        self.images.lock().expect("Images lock is poisoned")
+    }
+
+    // runs `update` on a planet which still has the expected revision
+    fn update_planet_with_revision<F>(
+        &self,
+        planet_id: ObjectId,
+        revision: i64,
+        update: F,
+    ) -> CustomResult<Planet>
+    where
+        F: FnOnce(&mut Planet) -> CustomResult<()>,
+    {
+        let mut planets = self.lock_planets();
+
+        let planet = planets
+            .get_mut(&planet_id)
+            .ok_or_else(|| get_planet_not_found(planet_id))?;
+
+        if planet.revision != revision {
+            return Err(super::get_precondition_failed(planet, revision));
+        }
+
+        let mut updated_planet = planet.clone();
+        update(&mut updated_planet)?;
+        updated_planet.revision = revision + 1;
+        updated_planet.updated_at = Some(DateTime::now());
+
+        *planet = updated_planet.clone();
+
+        Ok(updated_planet)
+    }
+}
+
+#[async_trait]
+impl UserRepository for InMemoryDb {
+    async fn get_user(&self, username: String) -> CustomResult<User> {
+        self.users
+            .get(&username)
+            .cloned()
+            .ok_or(CustomError::UserNotFound {
+                message: format!("Can't find a user by username: {}", username),
+            })
+    }
+}
+
+#[async_trait]
+impl PlanetRepository for InMemoryDb {
+    async fn get_planets(
+        &self,
+        planet_filter: &PlanetFilter,
+        page_request: &PageRequest,
+    ) -> CustomResult<Page<Planet>> {
+        let planets = self.lock_planets();
+
+        let mut items = planets
+            .values()
+            .filter(|planet| matches_filter(planet, planet_filter))
+            .filter(|planet| {
+                page_request.after.as_ref().map_or(true, |after| {
+                    compare_to_cursor(planet, after, page_request.sort) == Ordering::Greater
+                })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        items.sort_by(|left, right| compare_planets(left, right, page_request.sort));
+
+        let limit = page_request.limit as usize;
+
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items
+                .last()
+                .and_then(|planet| {
+                    Some(PageCursor {
+                        value: get_sort_value(planet, page_request.sort),
+                        id: planet.id?,
+                    })
+                })
+                .map(|cursor| cursor.encode())
+                .transpose()?
+        } else {
+            None
+        };
+
+        Ok(Page { items, next_cursor })
+    }
+
+    async fn get_all_planets(&self) -> CustomResult<BoxStream<'static, CustomResult<Planet>>> {
+        let planets = self.lock_planets().values().cloned().collect::<Vec<_>>();
+
+        Ok(stream::iter(planets.into_iter().map(Ok)).boxed())
+    }
+
+    async fn upsert_planet_by_name(
+        &self,
+        mut planet: Planet,
+    ) -> CustomResult<(Planet, Option<Planet>)> {
+        let mut planets = self.lock_planets();
+
+        let existing_planet = planets
+            .values()
+            .find(|existing_planet| existing_planet.name == planet.name)
+            .cloned();
+
+        let (planet_id, revision) = match &existing_planet {
+            Some(existing_planet) => (
+                existing_planet.id.unwrap_or_default(),
+                existing_planet.revision + 1,
+            ),
+            None => (ObjectId::new(), 1),
+        };
+
+        planet.id = Some(planet_id);
+        planet.revision = revision;
+        planet.updated_at = Some(DateTime::now());
+
+        planets.insert(planet_id, planet.clone());
+
+        Ok((planet, existing_planet))
+    }
+
+    async fn get_planet(&self, planet_id: ObjectId) -> CustomResult<Planet> {
+        self.lock_planets()
+            .get(&planet_id)
+            .cloned()
+            .ok_or_else(|| get_planet_not_found(planet_id))
+    }
+
+    async fn create_planet(&self, mut planet: Planet) -> CustomResult<Planet> {
+        let planet_id = ObjectId::new();
+
+        planet.id = Some(planet_id);
+        planet.updated_at = Some(DateTime::now());
+
+        self.lock_planets().insert(planet_id, planet.clone());
+
+        Ok(planet)
+    }
+
+    async fn delete_planet(&self, planet_id: ObjectId, revision: i64) -> CustomResult<Planet> {
+        let mut planets = self.lock_planets();
+
+        let planet = planets
+            .get(&planet_id)
+            .ok_or_else(|| get_planet_not_found(planet_id))?;
+
+        if planet.revision != revision {
+            return Err(super::get_precondition_failed(planet, revision));
+        }
+
+        planets
+            .remove(&planet_id)
+            .ok_or_else(|| get_planet_not_found(planet_id))
+    }
+
+    async fn update_planet(
+        &self,
+        planet_id: ObjectId,
+        planet: Planet,
+        revision: i64,
+    ) -> CustomResult<Planet> {
+        self.update_planet_with_revision(planet_id, revision, |existing_planet| {
+            *existing_planet = Planet {
+                id: Some(planet_id),
+                ..planet
+            };
+
+            Ok(())
+        })
+    }
+
+    async fn patch_planet(
+        &self,
+        planet_id: ObjectId,
+        set: Document,
+        unset: Document,
+        revision: i64,
+    ) -> CustomResult<Planet> {
+        if set.is_empty() && unset.is_empty() {
+            return self.get_planet(planet_id).await;
+        }
+
+        self.update_planet_with_revision(planet_id, revision, |planet| {
+            let mut document = Document::from(&*planet);
+
+            for (field, value) in set {
+                document.insert(field, value);
+            }
+            for (field, _) in unset {
+                document.remove(&field);
+            }
+
+            *planet = bson::from_document(document)?;
+
+            Ok(())
+        })
+    }
+
+    async fn get_all_satellites(
+        &self,
+        satellite_filter: &SatelliteFilter,
+    ) -> CustomResult<Vec<PlanetSatellite>> {
+        let landed_from = satellite_filter.landed_from.map(model::to_bson_date_time);
+        let landed_to = satellite_filter.landed_to.map(model::to_bson_date_time);
+
+        let planets = self.lock_planets();
+
+        let mut result = planets
+            .values()
+            .filter(|planet| {
+                satellite_filter.planet_types.is_empty()
+                    || satellite_filter.planet_types.contains(&planet.r#type)
+            })
+            .flat_map(|planet| {
+                planet
+                    .satellites
+                    .iter()
+                    .flatten()
+                    .map(move |satellite| (planet, satellite))
+            })
+            .filter(|(_, satellite)| {
+                if landed_from.is_none() && landed_to.is_none() {
+                    return true;
+                }
+
+                // like in MongoDB, a range never matches a missing landing date
+                satellite
+                    .first_spacecraft_landing_date
+                    .is_some_and(|landing_date| {
+                        landed_from.map_or(true, |landed_from| landing_date >= landed_from)
+                            && landed_to.map_or(true, |landed_to| landing_date <= landed_to)
+                    })
+            })
+            .map(|(planet, satellite)| PlanetSatellite {
+                planet_id: planet.id.unwrap_or_default(),
+                planet_name: planet.name.clone(),
+                planet_type: planet.r#type,
+                satellite: satellite.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        result.sort_by(|left, right| {
+            left.planet_name
+                .cmp(&right.planet_name)
+                .then_with(|| left.satellite.name.cmp(&right.satellite.name))
+        });
+
+        Ok(result)
+    }
+
+    async fn create_satellite(
+        &self,
+        planet_id: ObjectId,
+        satellite: Satellite,
+        revision: i64,
+    ) -> CustomResult<Planet> {
+        self.update_planet_with_revision(planet_id, revision, |planet| {
+            if planet.get_satellite(&satellite.name).is_some() {
+                return Err(super::get_satellite_conflict(&satellite.name));
+            }
+
+            planet
+                .satellites
+                .get_or_insert_with(Vec::new)
+                .push(satellite);
+
+            Ok(())
+        })
+    }
+
+    async fn update_satellite(
+        &self,
+        planet_id: ObjectId,
+        satellite_name: &str,
+        satellite: Satellite,
+        revision: i64,
+    ) -> CustomResult<Planet> {
+        self.update_planet_with_revision(planet_id, revision, |planet| {
+            if satellite.name != satellite_name && planet.get_satellite(&satellite.name).is_some() {
+                return Err(super::get_satellite_conflict(&satellite.name));
+            }
+
+            let existing_satellite = planet
+                .satellites
+                .iter_mut()
+                .flatten()
+                .find(|existing_satellite| existing_satellite.name == satellite_name)
+                .ok_or_else(|| super::get_satellite_not_found(satellite_name))?;
+
+            *existing_satellite = satellite;
+
+            Ok(())
+        })
+    }
+
+    async fn delete_satellite(
+        &self,
+        planet_id: ObjectId,
+        satellite_name: &str,
+        revision: i64,
+    ) -> CustomResult<Planet> {
+        self.update_planet_with_revision(planet_id, revision, |planet| {
+            if planet.get_satellite(satellite_name).is_none() {
+                return Err(super::get_satellite_not_found(satellite_name));
+            }
+
+            if let Some(satellites) = planet.satellites.as_mut() {
+                satellites.retain(|satellite| satellite.name != satellite_name);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[async_trait]
+impl ImageRepository for InMemoryDb {
+    async fn get_planet_image_info(
+        &self,
+        planet_id: ObjectId,
+    ) -> CustomResult<Option<PlanetImageInfo>> {
+        let image_info = self
+            .lock_images()
+            .get(&planet_id)
+            .map(|(image_info, _)| image_info.clone());
+
+        Ok(image_info)
+    }
+
+    async fn get_planet_image(&self, planet_id: ObjectId) -> CustomResult<Option<Vec<u8>>> {
+        let image = self
+            .lock_images()
+            .get(&planet_id)
+            .map(|(_, image)| image.clone());
+
+        Ok(image)
+    }
+
+    async fn put_planet_image(
+        &self,
+        planet_id: ObjectId,
+        image_info: PlanetImageInfo,
+        image: Vec<u8>,
+    ) -> CustomResult<()> {
+        self.lock_images().insert(planet_id, (image_info, image));
+
+        Ok(())
+    }
+
+    async fn delete_planet_image(&self, planet_id: ObjectId) -> CustomResult<()> {
+        self.lock_images().remove(&planet_id);
+
+        Ok(())
+    }
+}
+
+// seed files use MongoDB Extended JSON, e.g. `{ "$date": "..." }`
+fn parse_seed<T: serde::de::DeserializeOwned>(seed: &str) -> Vec<(Document, T)> {
+    let seed = serde_json::from_str::<Vec<JsonValue>>(seed).expect("Failed to parse seed data");
+
+    seed.into_iter()
+        .map(|value| {
+            let Ok(Bson::Document(document)) = Bson::try_from(value) else {
+                panic!("Seed data should contain JSON objects");
+            };
+            let item = bson::from_document(document.clone()).expect("Failed to parse seed item");
+
+            (document, item)
+        })
+        .collect()
+}
+
+fn get_planet_not_found(planet_id: ObjectId) -> CustomError {
+    CustomError::NotFound {
+        message: format!("Can't find a planet by id: {}", planet_id),
+    }
+}
+
+fn matches_filter(planet: &Planet, planet_filter: &PlanetFilter) -> bool {
+    let name = planet.name.to_lowercase();
+
+    let has_satellites = planet
+        .satellites
+        .as_ref()
+        .is_some_and(|satellites| !satellites.is_empty());
+
+    // radiuses of planets are kept as `f32` here, so the bounds are rounded the same way
+    (planet_filter.types.is_empty() || planet_filter.types.contains(&planet.r#type))
+        && planet_filter
+            .min_radius
+            .map_or(true, |min_radius| planet.mean_radius >= min_radius as f32)
+        && planet_filter
+            .max_radius
+            .map_or(true, |max_radius| planet.mean_radius <= max_radius as f32)
+        && planet_filter
+            .name_prefix
+            .as_ref()
+            .map_or(true, |prefix| name.starts_with(&prefix.to_lowercase()))
+        && planet_filter
+            .name_contains
+            .as_ref()
+            .map_or(true, |part| name.contains(&part.to_lowercase()))
+        && planet_filter
+            .has_satellites
+            .map_or(true, |expected| has_satellites == expected)
+        && planet_filter.satellite.as_ref().map_or(true, |satellite| {
+            planet
+                .satellites
+                .iter()
+                .flatten()
+                .any(|s| s.name.to_lowercase() == satellite.to_lowercase())
+        })
+}
+
+// the same (sort value, id) order which is used by the MongoDB cursor
+fn compare_planets(left: &Planet, right: &Planet, sort: Option<PlanetSort>) -> Ordering {
+    let ordering = match sort {
+        Some(PlanetSort::Name) => left.name.cmp(&right.name),
+        Some(PlanetSort::MeanRadius) | Some(PlanetSort::MeanRadiusDesc) => {
+            left.mean_radius.total_cmp(&right.mean_radius)
+        }
+        None => Ordering::Equal,
+    }
+    .then_with(|| left.id.cmp(&right.id));
+
+    match sort {
+        Some(sort) if sort.is_descending() => ordering.reverse(),
+        _ => ordering,
+    }
+}
+
+fn get_sort_value(planet: &Planet, sort: Option<PlanetSort>) -> Option<SortValue> {
+    match sort? {
+        PlanetSort::Name => Some(SortValue::Text(planet.name.clone())),
+        PlanetSort::MeanRadius | PlanetSort::MeanRadiusDesc => {
+            Some(SortValue::Number(planet.mean_radius.into()))
+        }
+    }
+}
+
+fn compare_to_cursor(planet: &Planet, cursor: &PageCursor, sort: Option<PlanetSort>) -> Ordering {
+    // the sort of a cursor is checked when it's parsed
+    let ordering = match (get_sort_value(planet, sort), &cursor.value) {
+        (Some(SortValue::Text(left)), Some(SortValue::Text(right))) => left.cmp(right),
+        (Some(SortValue::Number(left)), Some(SortValue::Number(right))) => left.total_cmp(right),
+        _ => Ordering::Equal,
+    }
+    .then_with(|| planet.id.cmp(&Some(cursor.id)));
+
+    match sort {
+        Some(sort) if sort.is_descending() => ordering.reverse(),
+        _ => ordering,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::PlanetType;
+
+    fn get_planet(name: &str, r#type: PlanetType, mean_radius: f32) -> Planet {
+        Planet {
+            id: None,
+            name: name.to_string(),
+            r#type,
+            mean_radius,
+            satellites: None,
+            image: None,
+            revision: 0,
+            updated_at: None,
+        }
+    }
+
+    fn get_planet_by_name(db: &InMemoryDb, name: &str) -> Planet {
+        db.lock_planets()
+            .values()
+            .find(|planet| planet.name == name)
+            .cloned()
+            .unwrap()
+    }
+
+    fn get_names(planets: &[Planet]) -> Vec<&str> {
+        planets.iter().map(|planet| planet.name.as_str()).collect()
+    }
+
+    async fn get_all_pages(
+        db: &InMemoryDb,
+        planet_filter: &PlanetFilter,
+        limit: u32,
+        sort: Option<PlanetSort>,
+    ) -> Vec<Planet> {
+        let mut planets = Vec::new();
+        let mut after = None;
+
+        loop {
+            let page_request = PageRequest::new(Some(limit), after.as_deref(), sort).unwrap();
+            let page = db.get_planets(planet_filter, &page_request).await.unwrap();
+
+            assert!(page.items.len() <= limit as usize);
+            planets.extend(page.items);
+
+            match page.next_cursor {
+                Some(next_cursor) => after = Some(next_cursor),
+                None => return planets,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn seeds_planets_and_users() {
+        let db = InMemoryDb::new();
+
+        let earth = get_planet_by_name(&db, "Earth");
+        assert_eq!(earth.r#type, PlanetType::TerrestrialPlanet);
+        assert!(earth.get_satellite("Moon").is_some());
+
+        assert!(db.get_user(String::from("mod_user")).await.is_ok());
+        assert!(matches!(
+            db.get_user(String::from("nobody")).await,
+            Err(CustomError::UserNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn creates_updates_and_deletes_planet() {
+        let db = InMemoryDb::new();
+
+        let planet = db
+            .create_planet(get_planet("Pluto", PlanetType::DwarfPlanet, 1188.3))
+            .await
+            .unwrap();
+        let planet_id = planet.id.unwrap();
+        assert_eq!(db.get_planet(planet_id).await.unwrap().name, "Pluto");
+
+        let updated_planet = db
+            .update_planet(
+                planet_id,
+                get_planet("Pluto", PlanetType::DwarfPlanet, 1188.0),
+                planet.revision,
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated_planet.revision, planet.revision + 1);
+        assert_eq!(updated_planet.mean_radius, 1188.0);
+
+        // the revision which has been read before the update is stale
+        assert!(matches!(
+            db.delete_planet(planet_id, planet.revision).await,
+            Err(CustomError::PreconditionFailed { .. })
+        ));
+
+        db.delete_planet(planet_id, updated_planet.revision)
+            .await
+            .unwrap();
+        assert!(matches!(
+            db.get_planet(planet_id).await,
+            Err(CustomError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn patches_only_provided_fields() {
+        let db = InMemoryDb::new();
+        let earth = get_planet_by_name(&db, "Earth");
+
+        let patched_planet = db
+            .patch_planet(
+                earth.id.unwrap(),
+                bson::doc! { "mean_radius": 6371.5 },
+                bson::doc! { "satellites": "" },
+                earth.revision,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(patched_planet.name, "Earth");
+        assert_eq!(patched_planet.mean_radius, 6371.5);
+        assert!(patched_planet.satellites.is_none());
+        assert_eq!(patched_planet.revision, earth.revision + 1);
+    }
+
+    #[tokio::test]
+    async fn upserts_planet_by_name() {
+        let db = InMemoryDb::new();
+        let mars = get_planet_by_name(&db, "Mars");
+
+        let (planet, old_planet) = db
+            .upsert_planet_by_name(get_planet("Mars", PlanetType::TerrestrialPlanet, 3390.0))
+            .await
+            .unwrap();
+        assert_eq!(planet.id, mars.id);
+        assert_eq!(planet.revision, mars.revision + 1);
+        assert_eq!(
+            old_planet.map(|old_planet| old_planet.mean_radius),
+            Some(3389.5)
+        );
+
+        let (planet, old_planet) = db
+            .upsert_planet_by_name(get_planet("Eris", PlanetType::DwarfPlanet, 1163.0))
+            .await
+            .unwrap();
+        assert_ne!(planet.id, mars.id);
+        assert_eq!(planet.revision, 1);
+        assert!(old_planet.is_none());
+    }
+
+    #[tokio::test]
+    async fn filters_planets() {
+        let db = InMemoryDb::new();
+        let sort = Some(PlanetSort::Name);
+
+        let filter = PlanetFilter {
+            types: vec![PlanetType::GasGiant, PlanetType::IceGiant],
+            ..Default::default()
+        };
+        let planets = get_all_pages(&db, &filter, 10, sort).await;
+        assert_eq!(
+            get_names(&planets),
+            ["Jupiter", "Neptune", "Saturn", "Uranus"]
+        );
+
+        // the bounds are inclusive
+        let filter = PlanetFilter {
+            min_radius: Some(2439.7),
+            max_radius: Some(6051.8),
+            ..Default::default()
+        };
+        let planets = get_all_pages(&db, &filter, 10, sort).await;
+        assert_eq!(get_names(&planets), ["Mars", "Mercury", "Venus"]);
+
+        let filter = PlanetFilter {
+            name_prefix: Some(String::from("MAR")),
+            ..Default::default()
+        };
+        let planets = get_all_pages(&db, &filter, 10, sort).await;
+        assert_eq!(get_names(&planets), ["Mars"]);
+
+        let filter = PlanetFilter {
+            name_contains: Some(String::from("ur")),
+            has_satellites: Some(true),
+            ..Default::default()
+        };
+        let planets = get_all_pages(&db, &filter, 10, sort).await;
+        assert_eq!(get_names(&planets), ["Saturn", "Uranus"]);
+
+        let filter = PlanetFilter {
+            has_satellites: Some(false),
+            ..Default::default()
+        };
+        let planets = get_all_pages(&db, &filter, 10, sort).await;
+        assert_eq!(get_names(&planets), ["Mercury", "Venus"]);
+
+        let filter = PlanetFilter {
+            satellite: Some(String::from("moon")),
+            ..Default::default()
+        };
+        let planets = get_all_pages(&db, &filter, 10, sort).await;
+        assert_eq!(get_names(&planets), ["Earth"]);
+    }
+
+    #[tokio::test]
+    async fn paginates_planets_in_sort_order() {
+        let db = InMemoryDb::new();
+        let filter = PlanetFilter::default();
+
+        let planets = get_all_pages(&db, &filter, 3, Some(PlanetSort::MeanRadius)).await;
+        assert_eq!(
+            get_names(&planets),
+            ["Mercury", "Mars", "Venus", "Earth", "Neptune", "Uranus", "Saturn", "Jupiter"]
+        );
+
+        let planets = get_all_pages(&db, &filter, 3, Some(PlanetSort::MeanRadiusDesc)).await;
+        assert_eq!(
+            get_names(&planets),
+            ["Jupiter", "Saturn", "Uranus", "Neptune", "Earth", "Venus", "Mars", "Mercury"]
+        );
+
+        // without a sort planets are returned in the order of ids
+        let planets = get_all_pages(&db, &filter, 1, None).await;
+        let mut ids = planets.iter().map(|planet| planet.id).collect::<Vec<_>>();
+        assert_eq!(ids.len(), 8);
+        ids.dedup();
+        assert_eq!(ids.len(), 8);
+        assert!(ids.windows(2).all(|ids| ids[0] < ids[1]));
+    }
+
+    #[tokio::test]
+    async fn continues_after_deleted_planet() {
+        let db = InMemoryDb::new();
+        let filter = PlanetFilter::default();
+        let sort = Some(PlanetSort::Name);
+
+        let page_request = PageRequest::new(Some(2), None, sort).unwrap();
+        let page = db.get_planets(&filter, &page_request).await.unwrap();
+        assert_eq!(get_names(&page.items), ["Earth", "Jupiter"]);
+
+        let jupiter = &page.items[1];
+        db.delete_planet(jupiter.id.unwrap(), jupiter.revision)
+            .await
+            .unwrap();
+
+        let page_request = PageRequest::new(Some(2), page.next_cursor.as_deref(), sort).unwrap();
+        let page = db.get_planets(&filter, &page_request).await.unwrap();
+        assert_eq!(get_names(&page.items), ["Mars", "Mercury"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_cursor() {
+        let db = InMemoryDb::new();
+        let filter = PlanetFilter::default();
+
+        let after = ObjectId::new().to_hex();
+        assert!(matches!(
+            PageRequest::new(None, Some(&after), None),
+            Err(CustomError::InvalidQuery { .. })
+        ));
+
+        // a cursor of one sort can't be used with another
+        let page_request = PageRequest::new(Some(2), None, Some(PlanetSort::Name)).unwrap();
+        let page = db.get_planets(&filter, &page_request).await.unwrap();
+        assert!(matches!(
+            PageRequest::new(
+                Some(2),
+                page.next_cursor.as_deref(),
+                Some(PlanetSort::MeanRadius)
+            ),
+            Err(CustomError::InvalidQuery { .. })
+        ));
+    }
+}
@@ -0,0 +1,740 @@
+use axum::async_trait;
+use futures::{io::Cursor, stream::BoxStream, StreamExt};
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, Bson, DateTime, Document},
+    error::Result,
+    gridfs::{FilesCollectionDocument, GridFsBucket},
+    options::{
+        FindOneAndReplaceOptions, FindOneAndUpdateOptions, FindOptions, GridFsBucketOptions,
+        GridFsFindOptions, GridFsUploadOptions, ReturnDocument,
+    },
+    Client, Collection,
+};
+
+use super::{ImageRepository, PlanetRepository, UserRepository};
+use crate::{
+    error::{CustomError, CustomResult},
+    model::{self, Planet, PlanetImageInfo, PlanetSatellite, Satellite, User},
+    query::{Page, PageCursor, PageRequest, PlanetFilter, SatelliteFilter, SortValue},
+};
+
+const DB_NAME: &str = "solar_system_info";
+const PLANETS_COLLECTION_NAME: &str = "planets";
+const USERS_COLLECTION_NAME: &str = "users";
+const IMAGES_BUCKET_NAME: &str = "images";
+
+// images of the seeded planets used to be found by the planet name
+const SEEDED_PLANET_IMAGES: [(&str, &str); 8] = [
+    ("Mercury", "mercury.jpg"),
+    ("Venus", "venus.jpg"),
+    ("Earth", "earth.jpg"),
+    ("Mars", "mars.jpg"),
+    ("Jupiter", "jupiter.jpg"),
+    ("Saturn", "saturn.jpg"),
+    ("Uranus", "uranus.jpg"),
+    ("Neptune", "neptune.jpg"),
+];
+
+#[derive(Clone)]
+pub struct MongoDbClient {
+    client: Client,
+}
+
+impl MongoDbClient {
+    pub async fn new(uri: String) -> Result<Self> {
+        Ok(Self {
+            client: Client::with_uri_str(uri).await?,
+        })
+    }
+
+    // brings the data of an existing database up to date, so it's safe to run on every start
+    pub async fn migrate(&self) -> Result<()> {
+        for (planet_name, image_key) in SEEDED_PLANET_IMAGES {
+            let filter = doc! { "name": planet_name, "image": { "$exists": false } };
+            let update = doc! {
+                "$set": {
+                    "image": { "key": image_key, "content_type": mime::IMAGE_JPEG.as_ref() },
+                },
+                "$inc": { "revision": 1_i64 },
+                "$currentDate": { "updated_at": true },
+            };
+
+            let result = self
+                .get_planets_collection()
+                .update_one(filter, update, None)
+                .await?;
+
+            if result.modified_count > 0 {
+                log::info!("Added image {} to planet {}", image_key, planet_name);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_planets_collection(&self) -> Collection<Planet> {
+        self.client
+            .database(DB_NAME)
+            .collection(PLANETS_COLLECTION_NAME)
+    }
+
+    fn get_planet_documents_collection(&self) -> Collection<Document> {
+        self.client
+            .database(DB_NAME)
+            .collection(PLANETS_COLLECTION_NAME)
+    }
+
+    fn get_users_collection(&self) -> Collection<User> {
+        self.client
+            .database(DB_NAME)
+            .collection(USERS_COLLECTION_NAME)
+    }
+
+    fn get_images_bucket(&self) -> GridFsBucket {
+        let options = GridFsBucketOptions::builder()
+            .bucket_name(String::from(IMAGES_BUCKET_NAME))
+            .build();
+
+        self.client.database(DB_NAME).gridfs_bucket(options)
+    }
+
+    // files are named by planet id, and only the latest upload is used
+    async fn find_planet_image_file(
+        &self,
+        planet_id: ObjectId,
+    ) -> CustomResult<Option<FilesCollectionDocument>> {
+        let filter = doc! { "filename": planet_id.to_hex() };
+        let options = GridFsFindOptions::builder()
+            .sort(doc! { "uploadDate": -1 })
+            .limit(1)
+            .build();
+
+        let mut files = self.get_images_bucket().find(filter, options).await?;
+
+        match files.next().await {
+            Some(file) => Ok(Some(file?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete_planet_image_files(
+        &self,
+        planet_id: ObjectId,
+        except_id: Option<&Bson>,
+    ) -> CustomResult<()> {
+        let bucket = self.get_images_bucket();
+
+        let mut filter = doc! { "filename": planet_id.to_hex() };
+        if let Some(except_id) = except_id {
+            filter.insert("_id", doc! { "$ne": except_id });
+        }
+
+        let mut files = bucket.find(filter, None).await?;
+
+        while let Some(file) = files.next().await {
+            bucket.delete(file?.id).await?;
+        }
+
+        Ok(())
+    }
+
+    // seeded planets may have no revision field yet
+    fn get_planet_revision_filter(planet_id: &ObjectId, revision: i64) -> Document {
+        if revision == 0 {
+            doc! { "_id": planet_id, "revision": { "$in": [0_i64, null] } }
+        } else {
+            doc! { "_id": planet_id, "revision": revision }
+        }
+    }
+
+    async fn get_precondition_failed(
+        &self,
+        planet_id: ObjectId,
+        revision: i64,
+    ) -> CustomResult<CustomError> {
+        let planet = self.get_planet(planet_id).await?;
+
+        Ok(super::get_precondition_failed(&planet, revision))
+    }
+
+    async fn update_planet_with_revision(
+        &self,
+        planet_id: ObjectId,
+        revision: i64,
+        filter: Document,
+        update: Document,
+        array_filters: Option<Vec<Document>>,
+    ) -> CustomResult<Option<Planet>> {
+        let mut revision_filter = Self::get_planet_revision_filter(&planet_id, revision);
+        revision_filter.extend(filter);
+
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .array_filters(array_filters)
+            .build();
+
+        let planet = self
+            .get_planets_collection()
+            .find_one_and_update(revision_filter, update, options)
+            .await?;
+
+        Ok(planet)
+    }
+}
+
+#[async_trait]
+impl UserRepository for MongoDbClient {
+    async fn get_user(&self, username: String) -> CustomResult<User> {
+        let filter = doc! { "username": username.clone() };
+
+        self.get_users_collection()
+            .find_one(filter, None)
+            .await?
+            .ok_or(CustomError::UserNotFound {
+                message: format!("Can't find a user by username: {}", username),
+            })
+    }
+}
+
+#[async_trait]
+impl PlanetRepository for MongoDbClient {
+    async fn get_planets(
+        &self,
+        planet_filter: &PlanetFilter,
+        page_request: &PageRequest,
+    ) -> CustomResult<Page<Planet>> {
+        let mut filter = Document::from(planet_filter);
+
+        let (sort_field, order, comparison) = match page_request.sort {
+            Some(sort) if sort.is_descending() => (Some(sort.field()), -1, "$lt"),
+            Some(sort) => (Some(sort.field()), 1, "$gt"),
+            None => (None, 1, "$gt"),
+        };
+
+        if let Some(after) = &page_request.after {
+            // continue after the (sort value, id) pair of the last returned planet
+            let cursor_filter = match (sort_field, &after.value) {
+                (Some(field), Some(value)) => {
+                    let value = Bson::from(value);
+
+                    doc! {
+                        "$or": [
+                            { field: { comparison: value.clone() } },
+                            { field: value, "_id": { comparison: after.id } },
+                        ]
+                    }
+                }
+                _ => doc! { "_id": { comparison: after.id } },
+            };
+
+            filter = doc! { "$and": [filter, cursor_filter] };
+        }
+
+        let sort = match sort_field {
+            Some(field) => doc! { field: order, "_id": order },
+            None => doc! { "_id": order },
+        };
+        let limit = page_request.limit as usize;
+
+        // one extra planet tells whether there is a next page
+        let options = FindOptions::builder()
+            .sort(sort)
+            .limit(limit as i64 + 1)
+            .build();
+
+        // documents are read as is, since a model may round the sort value, e.g. to `f32`
+        let mut documents = self
+            .get_planet_documents_collection()
+            .find(filter, options)
+            .await?;
+
+        let mut items = Vec::new();
+
+        while let Some(document) = documents.next().await {
+            items.push(document?);
+        }
+
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items
+                .last()
+                .and_then(|document| {
+                    Some(PageCursor {
+                        value: sort_field.and_then(|field| get_sort_value(document, field)),
+                        id: document.get_object_id("_id").ok()?,
+                    })
+                })
+                .map(|cursor| cursor.encode())
+                .transpose()?
+        } else {
+            None
+        };
+
+        let items = items
+            .into_iter()
+            .map(bson::from_document)
+            .collect::<std::result::Result<Vec<Planet>, _>>()?;
+
+        Ok(Page { items, next_cursor })
+    }
+
+    async fn get_all_planets(&self) -> CustomResult<BoxStream<'static, CustomResult<Planet>>> {
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+
+        let planets = self.get_planets_collection().find(None, options).await?;
+
+        Ok(planets
+            .map(|planet| planet.map_err(CustomError::from))
+            .boxed())
+    }
+
+    async fn upsert_planet_by_name(
+        &self,
+        mut planet: Planet,
+    ) -> CustomResult<(Planet, Option<Planet>)> {
+        planet.updated_at = Some(DateTime::now());
+
+        let mut set = Document::from(&planet);
+        for field in ["_id", "revision"] {
+            set.remove(field);
+        }
+
+        // the id of a created planet is chosen beforehand, so the replaced document alone
+        // tells what the upserted one is, and whether it has been created
+        let planet_id = ObjectId::new();
+        let filter = doc! { "name": &planet.name };
+        let mut update = doc! {
+            "$set": set,
+            "$inc": { "revision": 1_i64 },
+            "$setOnInsert": { "_id": planet_id },
+        };
+        if planet.image.is_none() {
+            update.insert("$unset", doc! { "image": "" });
+        }
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .build();
+
+        let old_planet = self
+            .get_planets_collection()
+            .find_one_and_update(filter, update, options)
+            .await?;
+
+        planet.id = Some(
+            old_planet
+                .as_ref()
+                .and_then(|old_planet| old_planet.id)
+                .unwrap_or(planet_id),
+        );
+        planet.revision = old_planet
+            .as_ref()
+            .map_or(0, |old_planet| old_planet.revision)
+            + 1;
+
+        Ok((planet, old_planet))
+    }
+
+    async fn get_planet(&self, planet_id: ObjectId) -> CustomResult<Planet> {
+        let filter = doc! { "_id": &planet_id };
+
+        self.get_planets_collection()
+            .find_one(filter, None)
+            .await?
+            .ok_or(CustomError::NotFound {
+                message: format!("Can't find a planet by id: {}", planet_id),
+            })
+    }
+
+    async fn create_planet(&self, mut planet: Planet) -> CustomResult<Planet> {
+        let collection = self.get_planets_collection();
+
+        planet.updated_at = Some(DateTime::now());
+
+        let insert_result = collection.insert_one(planet, None).await?;
+
+        let filter = doc! { "_id": &insert_result.inserted_id };
+        collection
+            .find_one(filter, None)
+            .await?
+            .ok_or(CustomError::NotFound {
+                message: String::from("Can't find created planet"),
+            })
+    }
+
+    async fn delete_planet(&self, planet_id: ObjectId, revision: i64) -> CustomResult<Planet> {
+        let filter = Self::get_planet_revision_filter(&planet_id, revision);
+
+        match self
+            .get_planets_collection()
+            .find_one_and_delete(filter, None)
+            .await?
+        {
+            Some(planet) => Ok(planet),
+            None => Err(self.get_precondition_failed(planet_id, revision).await?),
+        }
+    }
+
+    async fn update_planet(
+        &self,
+        planet_id: ObjectId,
+        mut planet: Planet,
+        revision: i64,
+    ) -> CustomResult<Planet> {
+        // the replacement must not change _id
+        planet.id = None;
+        planet.revision = revision + 1;
+        planet.updated_at = Some(DateTime::now());
+
+        let filter = Self::get_planet_revision_filter(&planet_id, revision);
+        let options = FindOneAndReplaceOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        match self
+            .get_planets_collection()
+            .find_one_and_replace(filter, planet, options)
+            .await?
+        {
+            Some(planet) => Ok(planet),
+            None => Err(self.get_precondition_failed(planet_id, revision).await?),
+        }
+    }
+
+    async fn patch_planet(
+        &self,
+        planet_id: ObjectId,
+        set: Document,
+        unset: Document,
+        revision: i64,
+    ) -> CustomResult<Planet> {
+        if set.is_empty() && unset.is_empty() {
+            return self.get_planet(planet_id).await;
+        }
+
+        let mut update = doc! {
+            "$inc": { "revision": 1_i64 },
+            "$currentDate": { "updated_at": true },
+        };
+        if !set.is_empty() {
+            update.insert("$set", set);
+        }
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+
+        match self
+            .update_planet_with_revision(planet_id, revision, Document::new(), update, None)
+            .await?
+        {
+            Some(planet) => Ok(planet),
+            None => Err(self.get_precondition_failed(planet_id, revision).await?),
+        }
+    }
+
+    async fn get_all_satellites(
+        &self,
+        satellite_filter: &SatelliteFilter,
+    ) -> CustomResult<Vec<PlanetSatellite>> {
+        let mut pipeline = Vec::new();
+
+        if !satellite_filter.planet_types.is_empty() {
+            let types = satellite_filter
+                .planet_types
+                .iter()
+                .map(|pt| pt.to_string())
+                .collect::<Vec<_>>();
+            pipeline.push(doc! { "$match": { "type": { "$in": types } } });
+        }
+
+        pipeline.push(doc! { "$unwind": "$satellites" });
+
+        let mut landing_date = Document::new();
+        if let Some(landed_from) = satellite_filter.landed_from {
+            landing_date.insert("$gte", model::to_bson_date_time(landed_from));
+        }
+        if let Some(landed_to) = satellite_filter.landed_to {
+            landing_date.insert("$lte", model::to_bson_date_time(landed_to));
+        }
+        if !landing_date.is_empty() {
+            pipeline.push(doc! {
+                "$match": { "satellites.first_spacecraft_landing_date": landing_date }
+            });
+        }
+
+        pipeline.push(doc! {
+            "$project": {
+                "_id": 0,
+                "planet_id": "$_id",
+                "planet_name": "$name",
+                "planet_type": "$type",
+                "satellite": "$satellites",
+            }
+        });
+        pipeline.push(doc! { "$sort": { "planet_name": 1, "satellite.name": 1 } });
+
+        let mut satellites = self
+            .get_planets_collection()
+            .aggregate(pipeline, None)
+            .await?;
+
+        let mut result = Vec::new();
+
+        while let Some(satellite) = satellites.next().await {
+            result.push(mongodb::bson::from_document(satellite?)?);
+        }
+
+        Ok(result)
+    }
+
+    async fn create_satellite(
+        &self,
+        planet_id: ObjectId,
+        satellite: Satellite,
+        revision: i64,
+    ) -> CustomResult<Planet> {
+        // $push can't be applied to a null array
+        let mut filter = Self::get_planet_revision_filter(&planet_id, revision);
+        filter.insert("satellites", Bson::Null);
+        let update = doc! { "$set": { "satellites": [] } };
+        self.get_planets_collection()
+            .update_one(filter, update, None)
+            .await?;
+
+        let filter = doc! { "satellites.name": { "$ne": &satellite.name } };
+        let update = doc! {
+            "$push": { "satellites": Document::from(&satellite) },
+            "$inc": { "revision": 1_i64 },
+            "$currentDate": { "updated_at": true },
+        };
+
+        match self
+            .update_planet_with_revision(planet_id, revision, filter, update, None)
+            .await?
+        {
+            Some(planet) => Ok(planet),
+            None => {
+                let planet = self.get_planet(planet_id).await?;
+
+                if planet.revision != revision {
+                    return Err(self.get_precondition_failed(planet_id, revision).await?);
+                }
+
+                Err(super::get_satellite_conflict(&satellite.name))
+            }
+        }
+    }
+
+    async fn update_satellite(
+        &self,
+        planet_id: ObjectId,
+        satellite_name: &str,
+        satellite: Satellite,
+        revision: i64,
+    ) -> CustomResult<Planet> {
+        let mut filter = doc! { "satellites.name": satellite_name };
+        if satellite.name != satellite_name {
+            filter = doc! {
+                "$and": [filter, { "satellites.name": { "$ne": &satellite.name } }]
+            };
+        }
+        // the positional `$` is ambiguous with two conditions on satellites, so the
+        // satellite to replace is matched by an array filter
+        let update = doc! {
+            "$set": { "satellites.$[satellite]": Document::from(&satellite) },
+            "$inc": { "revision": 1_i64 },
+            "$currentDate": { "updated_at": true },
+        };
+        let array_filters = vec![doc! { "satellite.name": satellite_name }];
+
+        match self
+            .update_planet_with_revision(planet_id, revision, filter, update, Some(array_filters))
+            .await?
+        {
+            Some(planet) => Ok(planet),
+            None => {
+                let planet = self.get_planet(planet_id).await?;
+
+                if planet.revision != revision {
+                    Err(self.get_precondition_failed(planet_id, revision).await?)
+                } else if planet.get_satellite(satellite_name).is_some() {
+                    Err(super::get_satellite_conflict(&satellite.name))
+                } else {
+                    Err(super::get_satellite_not_found(satellite_name))
+                }
+            }
+        }
+    }
+
+    async fn delete_satellite(
+        &self,
+        planet_id: ObjectId,
+        satellite_name: &str,
+        revision: i64,
+    ) -> CustomResult<Planet> {
+        let filter = doc! { "satellites.name": satellite_name };
+        let update = doc! {
+            "$pull": { "satellites": { "name": satellite_name } },
+            "$inc": { "revision": 1_i64 },
+            "$currentDate": { "updated_at": true },
+        };
+
+        match self
+            .update_planet_with_revision(planet_id, revision, filter, update, None)
+            .await?
+        {
+            Some(planet) => Ok(planet),
+            None => {
+                let planet = self.get_planet(planet_id).await?;
+
+                if planet.revision != revision {
+                    return Err(self.get_precondition_failed(planet_id, revision).await?);
+                }
+
+                Err(super::get_satellite_not_found(satellite_name))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ImageRepository for MongoDbClient {
+    async fn get_planet_image_info(
+        &self,
+        planet_id: ObjectId,
+    ) -> CustomResult<Option<PlanetImageInfo>> {
+        let Some(file) = self.find_planet_image_file(planet_id).await? else {
+            return Ok(None);
+        };
+
+        let metadata = file.metadata.unwrap_or_default();
+
+        Ok(Some(bson::from_document(metadata)?))
+    }
+
+    async fn get_planet_image(&self, planet_id: ObjectId) -> CustomResult<Option<Vec<u8>>> {
+        let Some(file) = self.find_planet_image_file(planet_id).await? else {
+            return Ok(None);
+        };
+
+        let mut image = Vec::with_capacity(file.length as usize);
+        self.get_images_bucket()
+            .download_to_futures_0_3_writer(file.id, &mut image)
+            .await?;
+
+        Ok(Some(image))
+    }
+
+    async fn put_planet_image(
+        &self,
+        planet_id: ObjectId,
+        image_info: PlanetImageInfo,
+        image: Vec<u8>,
+    ) -> CustomResult<()> {
+        let options = GridFsUploadOptions::builder()
+            .metadata(bson::to_document(&image_info)?)
+            .build();
+
+        let file_id = self
+            .get_images_bucket()
+            .upload_from_futures_0_3_reader(planet_id.to_hex(), Cursor::new(image), options)
+            .await?;
+
+        // the previous image is removed only when the new one is completely uploaded
+        self.delete_planet_image_files(planet_id, Some(&Bson::ObjectId(file_id)))
+            .await
+    }
+
+    async fn delete_planet_image(&self, planet_id: ObjectId) -> CustomResult<()> {
+        self.delete_planet_image_files(planet_id, None).await
+    }
+}
+
+impl From<&PlanetFilter> for Document {
+    fn from(
+        PlanetFilter {
+            types,
+            min_radius,
+            max_radius,
+            name_prefix,
+            name_contains,
+            has_satellites,
+            satellite,
+        }: &PlanetFilter,
+    ) -> Self {
+        let mut conditions = Vec::new();
+
+        match types.as_slice() {
+            [] => {}
+            [planet_type] => conditions.push(doc! { "type": planet_type.to_string() }),
+            types => {
+                let types = types.iter().map(|pt| pt.to_string()).collect::<Vec<_>>();
+                conditions.push(doc! { "type": { "$in": types } });
+            }
+        }
+
+        let mut radius = Document::new();
+        if let Some(min_radius) = min_radius {
+            radius.insert("$gte", min_radius);
+        }
+        if let Some(max_radius) = max_radius {
+            radius.insert("$lte", max_radius);
+        }
+        if !radius.is_empty() {
+            conditions.push(doc! { "mean_radius": radius });
+        }
+
+        if let Some(name_prefix) = name_prefix {
+            let pattern = format!("^{}", escape_regex(name_prefix));
+            conditions.push(doc! { "name": { "$regex": pattern, "$options": "i" } });
+        }
+        if let Some(name_contains) = name_contains {
+            let pattern = escape_regex(name_contains);
+            conditions.push(doc! { "name": { "$regex": pattern, "$options": "i" } });
+        }
+
+        if let Some(has_satellites) = has_satellites {
+            conditions.push(doc! { "satellites.0": { "$exists": has_satellites } });
+        }
+        if let Some(satellite) = satellite {
+            let pattern = format!("^{}$", escape_regex(satellite));
+            conditions.push(doc! { "satellites.name": { "$regex": pattern, "$options": "i" } });
+        }
+
+        match conditions.len() {
+            0 => Document::new(),
+            1 => conditions.remove(0),
+            _ => doc! { "$and": conditions },
+        }
+    }
+}
+
+impl From<&SortValue> for Bson {
+    fn from(value: &SortValue) -> Self {
+        match value {
+            SortValue::Number(number) => Bson::Double(*number),
+            SortValue::Text(text) => Bson::String(text.clone()),
+        }
+    }
+}
+
+fn get_sort_value(document: &Document, field: &str) -> Option<SortValue> {
+    match document.get(field)? {
+        Bson::Double(number) => Some(SortValue::Number(*number)),
+        Bson::Int32(number) => Some(SortValue::Number((*number).into())),
+        Bson::Int64(number) => Some(SortValue::Number(*number as f64)),
+        Bson::String(text) => Some(SortValue::Text(text.clone())),
+        _ => None,
+    }
+}
+
+fn escape_regex(value: &str) -> String {
+    value.chars().fold(String::new(), |mut escaped, c| {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+
+        escaped
+    })
+}
@@ -1,7 +1,10 @@
-use chrono::{NaiveDate, NaiveDateTime};
+use std::collections::HashSet;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use mongodb::bson::Document;
 use serde::{Deserialize, Serialize};
 
-use crate::model::{Planet, PlanetType, Satellite};
+use crate::model::{Planet, PlanetImage, PlanetSatellite, PlanetType, Satellite};
 
 #[derive(Serialize, Deserialize)]
 pub struct PlanetDto {
@@ -10,6 +13,8 @@ pub struct PlanetDto {
     pub r#type: PlanetType,
     pub mean_radius: f32,
     pub satellites: Option<Vec<SatelliteDto>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<PlanetImage>,
 }
 
 impl From<Planet> for PlanetDto {
@@ -20,6 +25,8 @@ impl From<Planet> for PlanetDto {
             r#type,
             mean_radius,
             satellites,
+            image,
+            ..
         }: Planet,
     ) -> Self {
         let id = id.map(|id| id.to_string());
@@ -32,31 +39,214 @@ impl From<Planet> for PlanetDto {
             r#type,
             mean_radius,
             satellites,
+            image,
+        }
+    }
+}
+
+impl PlanetDto {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err(String::from("Planet name should not be empty"));
+        }
+
+        if !self.mean_radius.is_finite() || self.mean_radius <= 0.0 {
+            return Err(format!(
+                "Planet mean radius should be positive, but got {}",
+                self.mean_radius
+            ));
+        }
+
+        let mut satellite_names = HashSet::new();
+        for satellite in self.satellites.iter().flatten() {
+            if satellite.name.trim().is_empty() {
+                return Err(String::from("Satellite name should not be empty"));
+            }
+
+            if !satellite_names.insert(&satellite.name) {
+                return Err(format!("Duplicated satellite: {}", satellite.name));
+            }
         }
+
+        if let Some(image) = &self.image {
+            if image.key.trim().is_empty() {
+                return Err(String::from("Image key should not be empty"));
+            }
+
+            let is_image = image
+                .content_type
+                .parse::<mime::Mime>()
+                .is_ok_and(|content_type| content_type.type_() == mime::IMAGE);
+            if !is_image {
+                return Err(format!(
+                    "Image content type should be an image, but got {}",
+                    image.content_type
+                ));
+            }
+        }
+
+        Ok(())
     }
 }
 
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Created,
+    Updated,
+    Rejected,
+}
+
 #[derive(Serialize)]
-pub struct PlanetMessage {
-    pub id: String,
-    pub name: String,
-    pub r#type: PlanetType,
+pub struct ImportReportLine {
+    pub line: usize,
+    pub status: ImportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
-impl From<&Planet> for PlanetMessage {
-    fn from(
-        Planet {
-            id, name, r#type, ..
-        }: &Planet,
-    ) -> Self {
-        PlanetMessage {
-            id: id
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub rejected: usize,
+    pub lines: Vec<ImportReportLine>,
+}
+
+impl ImportReport {
+    pub fn add_imported(&mut self, line: usize, planet: &Planet, is_created: bool) {
+        let status = if is_created {
+            self.created += 1;
+            ImportStatus::Created
+        } else {
+            self.updated += 1;
+            ImportStatus::Updated
+        };
+
+        self.lines.push(ImportReportLine {
+            line,
+            status,
+            id: planet.id.map(|id| id.to_string()),
+            error: None,
+        });
+    }
+
+    pub fn add_rejected(&mut self, line: usize, error: String) {
+        self.rejected += 1;
+
+        self.lines.push(ImportReportLine {
+            line,
+            status: ImportStatus::Rejected,
+            id: None,
+            error: Some(error),
+        });
+    }
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanetEventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlanetEvent {
+    pub kind: PlanetEventKind,
+    pub planet_id: String,
+    pub planet_type: PlanetType,
+    pub timestamp: DateTime<Utc>,
+    pub changed_fields: Vec<String>,
+}
+
+impl PlanetEvent {
+    fn new(kind: PlanetEventKind, planet: &Planet, changed_fields: Vec<String>) -> Self {
+        Self {
+            kind,
+            planet_id: planet
+                .id
                 .map(|id| id.to_string())
                 .expect("Planet.id is not specified"),
-            name: name.clone(),
-            r#type: *r#type,
+            planet_type: planet.r#type,
+            timestamp: Utc::now(),
+            changed_fields,
         }
     }
+
+    pub fn created(planet: &Planet) -> Self {
+        let changed_fields = Document::from(planet)
+            .keys()
+            .filter(|key| !Self::is_metadata_field(key))
+            .cloned()
+            .collect();
+
+        Self::new(PlanetEventKind::Created, planet, changed_fields)
+    }
+
+    pub fn updated(old_planet: &Planet, new_planet: &Planet) -> Self {
+        let (old_document, new_document) = (Document::from(old_planet), Document::from(new_planet));
+
+        let mut changed_fields = new_document
+            .iter()
+            .filter(|(key, value)| {
+                !Self::is_metadata_field(key) && old_document.get(key) != Some(value)
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        changed_fields.extend(
+            old_document
+                .keys()
+                .filter(|key| !new_document.contains_key(key))
+                .cloned(),
+        );
+
+        Self::new(PlanetEventKind::Updated, new_planet, changed_fields)
+    }
+
+    pub fn deleted(planet: &Planet) -> Self {
+        Self::new(PlanetEventKind::Deleted, planet, Vec::new())
+    }
+
+    fn is_metadata_field(field: &str) -> bool {
+        matches!(field, "_id" | "revision" | "updated_at")
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanetTopic {
+    All,
+    Planet(String),
+    Type(PlanetType),
+}
+
+impl PlanetTopic {
+    pub fn matches(&self, event: &PlanetEvent) -> bool {
+        match self {
+            PlanetTopic::All => true,
+            PlanetTopic::Planet(planet_id) => *planet_id == event.planet_id,
+            PlanetTopic::Type(planet_type) => *planet_type == event.planet_type,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum ClientMessage {
+    Subscribe { topic: PlanetTopic },
+    Unsubscribe { topic: PlanetTopic },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ServerMessage {
+    Subscribed { topic: PlanetTopic },
+    Unsubscribed { topic: PlanetTopic },
+    Event { id: String, event: PlanetEvent },
+    Error { message: String },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -73,9 +263,9 @@ impl From<Satellite> for SatelliteDto {
         }: Satellite,
     ) -> Self {
         let first_spacecraft_landing_date = first_spacecraft_landing_date.map(|dt| {
-            NaiveDateTime::from_timestamp_millis(dt.timestamp_millis())
+            DateTime::from_timestamp_millis(dt.timestamp_millis())
                 .unwrap()
-                .date()
+                .date_naive()
         });
 
         Self {
@@ -84,3 +274,30 @@ impl From<Satellite> for SatelliteDto {
         }
     }
 }
+
+#[derive(Serialize)]
+pub struct PlanetSatelliteDto {
+    pub planet_id: String,
+    pub planet_name: String,
+    pub planet_type: PlanetType,
+    #[serde(flatten)]
+    pub satellite: SatelliteDto,
+}
+
+impl From<PlanetSatellite> for PlanetSatelliteDto {
+    fn from(
+        PlanetSatellite {
+            planet_id,
+            planet_name,
+            planet_type,
+            satellite,
+        }: PlanetSatellite,
+    ) -> Self {
+        Self {
+            planet_id: planet_id.to_string(),
+            planet_name,
+            planet_type,
+            satellite: satellite.into(),
+        }
+    }
+}
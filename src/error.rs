@@ -1,12 +1,18 @@
 use std::fmt;
 
 use axum::{
+    extract::rejection::QueryRejection,
     http::StatusCode,
     response::{IntoResponse, Response},
+    Json,
 };
+use serde_json::json;
+
+use crate::rate_limit;
 
 pub type CustomResult<T> = std::result::Result<T, CustomError>;
 
+#[derive(Debug)]
 pub enum CustomError {
     MongoDbError(mongodb::error::Error),
     RedisError {
@@ -15,8 +21,34 @@ pub enum CustomError {
     NotFound {
         message: String,
     },
+    ImageNotFound {
+        message: String,
+    },
+    BadRequest {
+        message: String,
+    },
+    Conflict {
+        message: String,
+    },
+    PreconditionFailed {
+        message: String,
+    },
+    UnsupportedMediaType {
+        message: String,
+    },
+    InvalidQuery {
+        field: String,
+        message: String,
+    },
     SerdeError(serde_json::Error),
+    CsvError(csv::Error),
+    BsonError(mongodb::bson::de::Error),
+    BsonSerError(mongodb::bson::ser::Error),
+    UrlEncodedError(serde_urlencoded::ser::Error),
+    InvalidHeaderValue(axum::http::header::InvalidHeaderValue),
     TemplateError(askama::Error),
+    ImageError(image::ImageError),
+    TaskError(tokio::task::JoinError),
     InvalidAuthorizationHeader(http_auth_basic::AuthBasicError),
     UserNotFound {
         message: String,
@@ -26,8 +58,9 @@ pub enum CustomError {
     },
     HashError(argon2::password_hash::Error),
     TooManyRequests {
-        actual_count: u64,
-        permission_count: u64,
+        limit: u64,
+        reset_after_secs: u64,
+        retry_after_secs: u64,
     },
 }
 
@@ -39,19 +72,34 @@ impl fmt::Display for CustomError {
             match self {
                 CustomError::RedisError { message }
                 | CustomError::NotFound { message }
+                | CustomError::ImageNotFound { message }
+                | CustomError::BadRequest { message }
+                | CustomError::Conflict { message }
+                | CustomError::PreconditionFailed { message }
+                | CustomError::UnsupportedMediaType { message }
                 | CustomError::UserNotFound { message }
-                | CustomError::UserUnauthorized { message } => format!("{}", message),
+                | CustomError::UserUnauthorized { message } => message.to_string(),
+                CustomError::InvalidQuery { field, message } =>
+                    format!("Invalid query parameter {}: {}", field, message),
                 CustomError::MongoDbError(err) => err.to_string(),
                 CustomError::SerdeError(err) => err.to_string(),
+                CustomError::CsvError(err) => err.to_string(),
+                CustomError::BsonError(err) => err.to_string(),
+                CustomError::BsonSerError(err) => err.to_string(),
+                CustomError::UrlEncodedError(err) => err.to_string(),
+                CustomError::InvalidHeaderValue(err) => err.to_string(),
                 CustomError::TemplateError(err) => err.to_string(),
+                CustomError::ImageError(err) => err.to_string(),
+                CustomError::TaskError(err) => err.to_string(),
                 CustomError::InvalidAuthorizationHeader(err) => err.to_string(),
                 CustomError::HashError(err) => err.to_string(),
                 CustomError::TooManyRequests {
-                    actual_count,
-                    permission_count,
+                    limit,
+                    retry_after_secs,
+                    ..
                 } => format!(
-                    "Too many requests: actual_count = {}, permission_count: {}",
-                    actual_count, permission_count
+                    "Too many requests: limit = {}, retry after {} s",
+                    limit, retry_after_secs
                 ),
             }
         )
@@ -76,11 +124,38 @@ impl IntoResponse for CustomError {
                     .body(axum::body::Body::from("Unauthorized"))
                     .unwrap()
             }
-            CustomError::NotFound { message } => (StatusCode::NOT_FOUND, message),
-            CustomError::TooManyRequests { .. } => (
-                StatusCode::TOO_MANY_REQUESTS,
-                String::from("Too many requests"),
-            ),
+            CustomError::NotFound { message } | CustomError::ImageNotFound { message } => {
+                (StatusCode::NOT_FOUND, message)
+            }
+            CustomError::BadRequest { message } => (StatusCode::BAD_REQUEST, message),
+            CustomError::Conflict { message } => (StatusCode::CONFLICT, message),
+            CustomError::PreconditionFailed { message } => {
+                (StatusCode::PRECONDITION_FAILED, message)
+            }
+            CustomError::UnsupportedMediaType { message } => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, message)
+            }
+            CustomError::InvalidQuery { field, message } => {
+                let body = json!({ "field": field, "message": message });
+
+                return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+            }
+            CustomError::TooManyRequests {
+                limit,
+                reset_after_secs,
+                retry_after_secs,
+            } => {
+                // nothing remains of the limit, once a request is rejected
+                let headers =
+                    rate_limit::get_headers(limit, 0, reset_after_secs, Some(retry_after_secs));
+
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    headers,
+                    String::from("Too many requests"),
+                )
+                    .into_response();
+            }
             _ => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 String::from("Something went wrong"),
@@ -119,12 +194,63 @@ impl From<serde_json::Error> for CustomError {
     }
 }
 
+impl From<csv::Error> for CustomError {
+    fn from(err: csv::Error) -> Self {
+        Self::CsvError(err)
+    }
+}
+
+impl From<mongodb::bson::de::Error> for CustomError {
+    fn from(err: mongodb::bson::de::Error) -> Self {
+        Self::BsonError(err)
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for CustomError {
+    fn from(err: mongodb::bson::ser::Error) -> Self {
+        Self::BsonSerError(err)
+    }
+}
+
+impl From<QueryRejection> for CustomError {
+    fn from(err: QueryRejection) -> Self {
+        Self::InvalidQuery {
+            field: String::from("query"),
+            message: err.body_text(),
+        }
+    }
+}
+
+impl From<serde_urlencoded::ser::Error> for CustomError {
+    fn from(err: serde_urlencoded::ser::Error) -> Self {
+        Self::UrlEncodedError(err)
+    }
+}
+
+impl From<axum::http::header::InvalidHeaderValue> for CustomError {
+    fn from(err: axum::http::header::InvalidHeaderValue) -> Self {
+        Self::InvalidHeaderValue(err)
+    }
+}
+
 impl From<askama::Error> for CustomError {
     fn from(err: askama::Error) -> Self {
         Self::TemplateError(err)
     }
 }
 
+impl From<image::ImageError> for CustomError {
+    fn from(err: image::ImageError) -> Self {
+        Self::ImageError(err)
+    }
+}
+
+impl From<tokio::task::JoinError> for CustomError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Self::TaskError(err)
+    }
+}
+
 impl From<http_auth_basic::AuthBasicError> for CustomError {
     fn from(err: http_auth_basic::AuthBasicError) -> Self {
         Self::InvalidAuthorizationHeader(err)
@@ -1,21 +1,47 @@
-use std::sync::Arc;
+use std::{collections::HashSet, convert::Infallible, io, sync::Arc};
 
 use askama::Template;
 use axum::{
-    extract::{Path, Query, State},
-    http::header,
-    response::{Html, IntoResponse},
+    body::{Body, Bytes},
+    extract::{
+        rejection::QueryRejection,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
     Json,
 };
-use serde::Deserialize;
+use chrono::NaiveDate;
+use futures::{future, stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncBufReadExt;
+use tokio_util::io::StreamReader;
 
 use crate::{
-    dto::PlanetDto,
-    error::CustomResult,
-    model::{Planet, PlanetType},
-    services::{basic_auth::BasicAuth, rate_limit_service::RateLimit, AppState},
+    bulk::{BulkFormat, PlanetRecordParser},
+    conditional::{self, Validators},
+    dto::{
+        ClientMessage, ImportReport, PlanetDto, PlanetEvent, PlanetSatelliteDto, SatelliteDto,
+        ServerMessage,
+    },
+    error::{CustomError, CustomResult},
+    image_processing::ImageVariant,
+    model::{Planet, PlanetType, Satellite},
+    query::{Page, PageRequest, PlanetFilter, PlanetSort, SatelliteFilter},
+    services::{basic_auth::BasicAuth, AppState},
 };
 
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+const NEXT_CURSOR: &str = "X-Next-Cursor";
+// clients should revalidate planets, as they can be changed at any moment
+const PLANET_CACHE_CONTROL: &str = "no-cache";
+// images can be replaced at the same URL, so shared caches may keep them, but revalidate
+const IMAGE_CACHE_CONTROL: &str = "public, no-cache";
+
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate;
@@ -27,30 +53,325 @@ pub async fn index() -> CustomResult<Html<String>> {
     Ok(Html(result))
 }
 
+pub async fn events(
+    headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> CustomResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let last_event_id = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|header| header.to_str().ok())
+        .map(String::from);
+
+    let stream = state
+        .planet_service
+        .subscribe_to_planet_events(last_event_id)
+        .await?
+        .map(|(id, event)| Ok(Event::default().id(id).data(event)));
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
 #[derive(Deserialize)]
-pub struct PlanetTypeQueryParam {
-    r#type: Option<PlanetType>,
+pub struct LastEventIdQueryParam {
+    last_event_id: Option<String>,
+}
+
+pub async fn ws(
+    ws: WebSocketUpgrade,
+    Query(param): Query<LastEventIdQueryParam>,
+    State(state): State<Arc<AppState>>,
+) -> CustomResult<impl IntoResponse> {
+    let events = state
+        .planet_service
+        .subscribe_to_planet_events(param.last_event_id)
+        .await?;
+
+    Ok(ws.on_upgrade(|socket| handle_socket(socket, events)))
+}
+
+async fn handle_socket(mut socket: WebSocket, events: impl Stream<Item = (String, String)>) {
+    tokio::pin!(events);
+    let mut topics = HashSet::new();
+    // events are read only after the first subscription, so replayed ones aren't dropped
+    let mut subscribed = false;
+
+    loop {
+        let reply = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(ClientMessage::Subscribe { topic }) => {
+                        topics.insert(topic.clone());
+                        subscribed = true;
+                        ServerMessage::Subscribed { topic }
+                    }
+                    Ok(ClientMessage::Unsubscribe { topic }) => {
+                        topics.remove(&topic);
+                        ServerMessage::Unsubscribed { topic }
+                    }
+                    Err(err) => ServerMessage::Error {
+                        message: err.to_string(),
+                    },
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = events.next(), if subscribed => match event {
+                Some((id, event)) => match serde_json::from_str::<PlanetEvent>(&event) {
+                    Ok(event) if topics.iter().any(|topic| topic.matches(&event)) => {
+                        ServerMessage::Event { id, event }
+                    }
+                    Ok(_) => continue,
+                    Err(err) => {
+                        log::error!("Failed to parse planet event {}: {}", id, err);
+                        continue;
+                    }
+                },
+                None => break,
+            },
+        };
+
+        let message = match serde_json::to_string(&reply) {
+            Ok(message) => message,
+            Err(err) => {
+                log::error!("Failed to serialize WebSocket message: {}", err);
+                continue;
+            }
+        };
+
+        if socket.send(Message::Text(message)).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlanetsQueryParams {
+    // comma-separated list of planet types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r#type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_radius: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_radius: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name_contains: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    has_satellites: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    satellite: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<PlanetSort>,
+}
+
+fn parse_planet_types(field: &str, types: Option<&str>) -> CustomResult<Vec<PlanetType>> {
+    types
+        .iter()
+        .flat_map(|types| types.split(','))
+        .map(|planet_type| {
+            planet_type
+                .trim()
+                .parse()
+                .map_err(|message| CustomError::InvalidQuery {
+                    field: field.to_string(),
+                    message,
+                })
+        })
+        .collect()
+}
+
+impl TryFrom<&PlanetsQueryParams> for PlanetFilter {
+    type Error = CustomError;
+
+    fn try_from(params: &PlanetsQueryParams) -> Result<Self, Self::Error> {
+        let types = parse_planet_types("type", params.r#type.as_deref())?;
+
+        let filter = PlanetFilter {
+            types,
+            min_radius: params.min_radius,
+            max_radius: params.max_radius,
+            name_prefix: params.name_prefix.clone(),
+            name_contains: params.name_contains.clone(),
+            has_satellites: params.has_satellites,
+            satellite: params.satellite.clone(),
+        };
+        filter.validate()?;
+
+        Ok(filter)
+    }
 }
 
 pub async fn get_planets(
-    Query(param): Query<PlanetTypeQueryParam>,
-    _: RateLimit,
+    uri: Uri,
+    params: Result<Query<PlanetsQueryParams>, QueryRejection>,
     State(state): State<Arc<AppState>>,
-) -> CustomResult<Json<Vec<PlanetDto>>> {
-    let planets = state.planet_service.get_planets(param.r#type).await?;
+) -> CustomResult<impl IntoResponse> {
+    let Query(params) = params?;
 
-    let result = planets.into_iter().map(PlanetDto::from).collect::<Vec<_>>();
+    let planet_filter = PlanetFilter::try_from(&params)?;
+    let page_request = PageRequest::new(params.limit, params.after.as_deref(), params.sort)?;
 
-    Ok(Json(result))
+    let Page { items, next_cursor } = state
+        .planet_service
+        .get_planets(&planet_filter, &page_request)
+        .await?;
+
+    let mut headers = HeaderMap::new();
+
+    if let Some(next_cursor) = next_cursor {
+        let next_params = PlanetsQueryParams {
+            limit: Some(page_request.limit),
+            after: Some(next_cursor.clone()),
+            ..params
+        };
+        let link = format!(
+            "<{}?{}>; rel=\"next\"",
+            uri.path(),
+            serde_urlencoded::to_string(&next_params)?
+        );
+
+        headers.insert(header::LINK, HeaderValue::from_str(&link)?);
+        headers.insert(NEXT_CURSOR, HeaderValue::from_str(&next_cursor)?);
+    }
+
+    let result = items.into_iter().map(PlanetDto::from).collect::<Vec<_>>();
+
+    Ok((headers, Json(result)))
+}
+
+pub async fn import_planets(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    _auth: BasicAuth,
+    body: Body,
+) -> CustomResult<Json<ImportReport>> {
+    let format = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(BulkFormat::from_import_content_type)
+        .ok_or(CustomError::UnsupportedMediaType {
+            message: String::from("Planets can be imported only from NDJSON or CSV"),
+        })?;
+
+    let body = body
+        .into_data_stream()
+        .map(|chunk| chunk.map_err(|err| io::Error::new(io::ErrorKind::Other, err)));
+    let mut lines = StreamReader::new(body).lines();
+
+    let mut parser = PlanetRecordParser::new(format);
+    let mut report = ImportReport::default();
+    let mut line_number = 0;
+
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|err| CustomError::BadRequest {
+            message: format!("Failed to read line {}: {}", line_number + 1, err),
+        })?
+    {
+        line_number += 1;
+
+        let planet_dto = match parser.parse_line(&line) {
+            Some(Ok(planet_dto)) => planet_dto,
+            Some(Err(err)) => {
+                report.add_rejected(line_number, err);
+                continue;
+            }
+            None => continue,
+        };
+
+        let planet = Planet::from(PlanetDto {
+            id: None,
+            ..planet_dto
+        });
+
+        match state.planet_service.import_planet(planet).await {
+            Ok((planet, is_created)) => report.add_imported(line_number, &planet, is_created),
+            // details of storage errors are of no use to a client
+            Err(err) => {
+                log::error!("Failed to import planet on line {}: {}", line_number, err);
+                report.add_rejected(line_number, String::from("Failed to import the planet"));
+            }
+        }
+    }
+
+    Ok(Json(report))
+}
+
+#[derive(Deserialize)]
+pub struct ExportQueryParams {
+    format: Option<BulkFormat>,
+}
+
+pub async fn export_planets(
+    params: Result<Query<ExportQueryParams>, QueryRejection>,
+    State(state): State<Arc<AppState>>,
+) -> CustomResult<impl IntoResponse> {
+    let Query(params) = params?;
+    let format = params.format.unwrap_or(BulkFormat::Json);
+
+    let planets = state
+        .planet_service
+        .export_planets()
+        .await?
+        .enumerate()
+        .map(move |(i, planet)| {
+            planet
+                .and_then(|planet| format.encode(&PlanetDto::from(planet), i == 0))
+                .map_err(|err| {
+                    log::error!("Failed to export planet: {}", err);
+                    io::Error::new(io::ErrorKind::Other, err.to_string())
+                })
+        });
+
+    let body = stream::once(future::ready(Ok(format.prefix().as_bytes().to_vec())))
+        .chain(planets)
+        .chain(stream::once(future::ready(Ok(format
+            .suffix()
+            .as_bytes()
+            .to_vec()))));
+
+    let content_disposition = format!("attachment; filename=\"planets.{}\"", format.extension());
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition),
+        ],
+        Body::from_stream(body),
+    ))
 }
 
 pub async fn get_planet(
     Path(planet_id): Path<String>,
+    headers: HeaderMap,
     State(state): State<Arc<AppState>>,
-) -> CustomResult<Json<PlanetDto>> {
-    let result = state.planet_service.get_planet(&planet_id).await?.into();
+) -> CustomResult<Response> {
+    let planet = state.planet_service.get_planet(&planet_id).await?;
 
-    Ok(Json(result))
+    let validators = Validators {
+        etag: planet.etag()?,
+        last_modified: planet.last_modified(),
+    };
+    let mut response_headers = validators.headers(PLANET_CACHE_CONTROL)?;
+
+    if validators.is_not_modified(&headers) {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()),
+    );
+
+    let body = serde_json::to_vec(&PlanetDto::from(planet))?;
+
+    Ok((response_headers, body).into_response())
 }
 
 pub async fn create_planet(
@@ -68,14 +389,34 @@ pub async fn create_planet(
 pub async fn update_planet(
     State(state): State<Arc<AppState>>,
     Path(planet_id): Path<String>,
+    headers: HeaderMap,
     _auth: BasicAuth,
     Json(planet_dto): Json<PlanetDto>,
 ) -> CustomResult<Json<PlanetDto>> {
     let planet = Planet::from(planet_dto);
+    let if_match = conditional::get_if_match(&headers);
+
+    let result = state
+        .planet_service
+        .update_planet(&planet_id, planet, if_match.as_deref())
+        .await?
+        .into();
+
+    Ok(Json(result))
+}
+
+pub async fn patch_planet(
+    State(state): State<Arc<AppState>>,
+    Path(planet_id): Path<String>,
+    headers: HeaderMap,
+    _auth: BasicAuth,
+    Json(patch): Json<serde_json::Value>,
+) -> CustomResult<Json<PlanetDto>> {
+    let if_match = conditional::get_if_match(&headers);
 
     let result = state
         .planet_service
-        .update_planet(&planet_id, planet)
+        .patch_planet(&planet_id, patch, if_match.as_deref())
         .await?
         .into();
 
@@ -83,23 +424,200 @@ pub async fn update_planet(
 }
 
 pub async fn delete_planet(
+    Path(planet_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    _auth: BasicAuth,
+) -> CustomResult<()> {
+    let if_match = conditional::get_if_match(&headers);
+
+    state
+        .planet_service
+        .delete_planet(&planet_id, if_match.as_deref())
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_satellites(
+    Path(planet_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> CustomResult<Json<Vec<SatelliteDto>>> {
+    let satellites = state.planet_service.get_satellites(&planet_id).await?;
+
+    let result = satellites
+        .into_iter()
+        .map(SatelliteDto::from)
+        .collect::<Vec<_>>();
+
+    Ok(Json(result))
+}
+
+#[derive(Deserialize)]
+pub struct SatellitesQueryParams {
+    // comma-separated list of planet types
+    planet_type: Option<String>,
+    landed_from: Option<NaiveDate>,
+    landed_to: Option<NaiveDate>,
+}
+
+pub async fn get_all_satellites(
+    params: Result<Query<SatellitesQueryParams>, QueryRejection>,
+    State(state): State<Arc<AppState>>,
+) -> CustomResult<Json<Vec<PlanetSatelliteDto>>> {
+    let Query(params) = params?;
+
+    let satellite_filter = SatelliteFilter {
+        planet_types: parse_planet_types("planet_type", params.planet_type.as_deref())?,
+        landed_from: params.landed_from,
+        landed_to: params.landed_to,
+    };
+    satellite_filter.validate()?;
+
+    let satellites = state
+        .planet_service
+        .get_all_satellites(&satellite_filter)
+        .await?;
+
+    let result = satellites
+        .into_iter()
+        .map(PlanetSatelliteDto::from)
+        .collect::<Vec<_>>();
+
+    Ok(Json(result))
+}
+
+pub async fn get_satellite(
+    Path((planet_id, satellite_name)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> CustomResult<Json<SatelliteDto>> {
+    let result = state
+        .planet_service
+        .get_satellite(&planet_id, &satellite_name)
+        .await?
+        .into();
+
+    Ok(Json(result))
+}
+
+pub async fn create_satellite(
     Path(planet_id): Path<String>,
     State(state): State<Arc<AppState>>,
     _auth: BasicAuth,
+    Json(satellite_dto): Json<SatelliteDto>,
+) -> CustomResult<Json<SatelliteDto>> {
+    let satellite = Satellite::from(satellite_dto);
+
+    let result = state
+        .planet_service
+        .create_satellite(&planet_id, satellite)
+        .await?
+        .into();
+
+    Ok(Json(result))
+}
+
+pub async fn update_satellite(
+    Path((planet_id, satellite_name)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    _auth: BasicAuth,
+    Json(satellite_dto): Json<SatelliteDto>,
+) -> CustomResult<Json<SatelliteDto>> {
+    let satellite = Satellite::from(satellite_dto);
+
+    let result = state
+        .planet_service
+        .update_satellite(&planet_id, &satellite_name, satellite)
+        .await?
+        .into();
+
+    Ok(Json(result))
+}
+
+pub async fn delete_satellite(
+    Path((planet_id, satellite_name)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    _auth: BasicAuth,
 ) -> CustomResult<()> {
-    state.planet_service.delete_planet(&planet_id).await?;
+    state
+        .planet_service
+        .delete_satellite(&planet_id, &satellite_name)
+        .await?;
 
     Ok(())
 }
 
+#[derive(Deserialize)]
+pub struct ImageQueryParams {
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
 pub async fn get_image_of_planet(
     Path(planet_id): Path<String>,
+    params: Result<Query<ImageQueryParams>, QueryRejection>,
+    headers: HeaderMap,
     State(state): State<Arc<AppState>>,
-) -> CustomResult<impl IntoResponse> {
-    let result = (
-        [(header::CONTENT_TYPE, mime::IMAGE_JPEG.as_ref())],
-        state.planet_service.get_planet_image(&planet_id).await?,
+) -> CustomResult<Response> {
+    let Query(params) = params?;
+
+    let image_info = state
+        .planet_service
+        .get_planet_image_info(&planet_id)
+        .await?;
+
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok());
+    let variant = ImageVariant::new(
+        &image_info.content_type,
+        accept,
+        params.width,
+        params.height,
+    )?;
+
+    let validators = Validators {
+        etag: variant.get_etag(&image_info),
+        last_modified: image_info.last_modified(),
+    };
+    let mut response_headers = validators.headers(IMAGE_CACHE_CONTROL)?;
+    response_headers.insert(header::VARY, HeaderValue::from_static("Accept"));
+
+    // the image itself isn't read if a client already has it
+    if validators.is_not_modified(&headers) {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(variant.content_type()),
     );
 
-    Ok(result)
+    let image = state
+        .planet_service
+        .get_planet_image_variant(&planet_id, &image_info, variant)
+        .await?;
+
+    Ok((response_headers, image).into_response())
+}
+
+pub async fn upload_image_of_planet(
+    State(state): State<Arc<AppState>>,
+    Path(planet_id): Path<String>,
+    headers: HeaderMap,
+    _auth: BasicAuth,
+    image: Bytes,
+) -> CustomResult<Response> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok());
+
+    let image_info = state
+        .planet_service
+        .upload_planet_image(&planet_id, content_type, image.to_vec())
+        .await?;
+
+    let response_headers = [(header::ETAG, HeaderValue::from_str(&image_info.etag)?)];
+
+    Ok((StatusCode::NO_CONTENT, response_headers).into_response())
 }
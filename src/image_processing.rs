@@ -0,0 +1,199 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageError, ImageFormat, ImageOutputFormat, Rgba, RgbaImage};
+
+use crate::{
+    conditional,
+    error::{CustomError, CustomResult},
+    model::{PlanetImageInfo, PlanetType},
+};
+
+const MAX_IMAGE_DIMENSION: u32 = 4096;
+const JPEG_QUALITY: u8 = 85;
+const PLACEHOLDER_SIZE: u32 = 256;
+
+// formats which an image can be converted to, the preferred ones go first
+const NEGOTIABLE_FORMATS: [ImageFormat; 3] =
+    [ImageFormat::WebP, ImageFormat::Png, ImageFormat::Jpeg];
+
+// a resized and/or converted representation of an image
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ImageVariant {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub format: ImageFormat,
+}
+
+impl ImageVariant {
+    pub fn new(
+        content_type: &str,
+        accept: Option<&str>,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> CustomResult<Self> {
+        for (field, dimension) in [("width", width), ("height", height)] {
+            if dimension.is_some_and(|d| d == 0 || d > MAX_IMAGE_DIMENSION) {
+                return Err(CustomError::InvalidQuery {
+                    field: field.to_string(),
+                    message: format!("Value should be from 1 to {}", MAX_IMAGE_DIMENSION),
+                });
+            }
+        }
+
+        let original_format =
+            ImageFormat::from_mime_type(content_type).ok_or(CustomError::UnsupportedMediaType {
+                message: format!("Unsupported image type: {}", content_type),
+            })?;
+
+        Ok(Self {
+            width,
+            height,
+            format: negotiate_format(original_format, accept),
+        })
+    }
+
+    pub fn is_resized(&self) -> bool {
+        self.width.is_some() || self.height.is_some()
+    }
+
+    pub fn is_original(&self, image_info: &PlanetImageInfo) -> bool {
+        !self.is_resized() && self.content_type() == image_info.content_type
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        self.format.to_mime_type()
+    }
+
+    // e.g. "200x.webp" for an image which is 200 pixels wide
+    pub fn key(&self) -> String {
+        let dimension = |d: Option<u32>| d.map(|d| d.to_string()).unwrap_or_default();
+
+        format!(
+            "{}x{}.{}",
+            dimension(self.width),
+            dimension(self.height),
+            self.format.extensions_str()[0]
+        )
+    }
+
+    pub fn get_etag(&self, image_info: &PlanetImageInfo) -> String {
+        if self.is_original(image_info) {
+            return image_info.etag.clone();
+        }
+
+        conditional::get_strong_etag(format!("{}{}", image_info.etag, self.key()).as_bytes())
+    }
+
+    // fits the image into the requested size keeping its aspect ratio, but never enlarges it
+    pub fn apply(&self, image: &[u8]) -> Result<Vec<u8>, ImageError> {
+        let mut image = image::load_from_memory(image)?;
+
+        if self.is_resized() {
+            let width = self.width.unwrap_or(u32::MAX).min(image.width());
+            let height = self.height.unwrap_or(u32::MAX).min(image.height());
+
+            image = image.thumbnail(width, height);
+        }
+
+        // encoders support only 8-bit colors, and JPEG has no alpha channel
+        let image = match self.format {
+            ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()),
+            _ if image.color().has_alpha() => DynamicImage::ImageRgba8(image.to_rgba8()),
+            _ => DynamicImage::ImageRgb8(image.to_rgb8()),
+        };
+
+        let output_format = match self.format {
+            ImageFormat::Jpeg => ImageOutputFormat::Jpeg(JPEG_QUALITY),
+            format => ImageOutputFormat::from(format),
+        };
+
+        let mut result = Cursor::new(Vec::new());
+        image.write_to(&mut result, output_format)?;
+
+        Ok(result.into_inner())
+    }
+}
+
+// a disc colored by the planet type on a transparent background
+pub fn generate_placeholder(planet_type: PlanetType) -> Result<Vec<u8>, ImageError> {
+    let color = match planet_type {
+        PlanetType::TerrestrialPlanet => Rgba([176, 124, 84, 255]),
+        PlanetType::GasGiant => Rgba([222, 170, 105, 255]),
+        PlanetType::IceGiant => Rgba([120, 180, 220, 255]),
+        PlanetType::DwarfPlanet => Rgba([160, 160, 160, 255]),
+    };
+
+    let center = PLACEHOLDER_SIZE as f32 / 2.0;
+    let radius = center * 0.8;
+
+    let image = RgbaImage::from_fn(PLACEHOLDER_SIZE, PLACEHOLDER_SIZE, |x, y| {
+        let (dx, dy) = (x as f32 + 0.5 - center, y as f32 + 0.5 - center);
+
+        if dx * dx + dy * dy <= radius * radius {
+            color
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    });
+
+    let mut result = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(image).write_to(&mut result, ImageOutputFormat::Png)?;
+
+    Ok(result.into_inner())
+}
+
+// the original format is kept unless a client prefers another one
+fn negotiate_format(original_format: ImageFormat, accept: Option<&str>) -> ImageFormat {
+    let Some(accept) = accept else {
+        return original_format;
+    };
+
+    let media_ranges = parse_accept(accept);
+
+    let candidates = std::iter::once(original_format)
+        .chain(NEGOTIABLE_FORMATS)
+        .map(|format| (format, get_quality(&media_ranges, format.to_mime_type())));
+
+    // `max_by` returns the last maximum, so the candidates are reversed to keep the first one
+    candidates
+        .filter(|(_, quality)| *quality > 0.0)
+        .rev()
+        .max_by(|(_, left), (_, right)| left.total_cmp(right))
+        .map_or(original_format, |(format, _)| format)
+}
+
+// (media range, quality) pairs of an Accept header
+fn parse_accept(accept: &str) -> Vec<(String, f32)> {
+    accept
+        .split(',')
+        .filter_map(|media_range| {
+            let mut parts = media_range.split(';').map(str::trim);
+            let media_range = parts.next().filter(|r| !r.is_empty())?.to_lowercase();
+
+            let quality = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            Some((media_range, quality))
+        })
+        .collect()
+}
+
+// the most specific media range matching the type defines its quality
+fn get_quality(media_ranges: &[(String, f32)], media_type: &str) -> f32 {
+    let type_range = media_type
+        .split_once('/')
+        .map(|(t, _)| format!("{}/*", t))
+        .unwrap_or_default();
+
+    [media_type, type_range.as_str(), "*/*"]
+        .iter()
+        .find_map(|range| {
+            media_ranges
+                .iter()
+                .find(|(media_range, _)| media_range == range)
+                .map(|(_, quality)| *quality)
+        })
+        .unwrap_or(0.0)
+}
@@ -1,9 +1,15 @@
+mod bulk;
+mod cache;
+mod client_ip;
+mod conditional;
 mod db;
 mod dto;
 mod error;
 mod handlers;
+mod image_processing;
 mod model;
-mod redis;
+mod query;
+mod rate_limit;
 mod services;
 
 use std::{
@@ -12,13 +18,42 @@ use std::{
     sync::Arc,
 };
 
-use axum::{routing::get, Router};
+use axum::{
+    extract::DefaultBodyLimit,
+    routing::{get, post},
+    Router,
+};
 use tokio::net::TcpListener;
 
-use crate::services::AppState;
+use crate::{
+    cache::{Cache, RateLimiter},
+    client_ip::{ClientIpLayer, ClientIpResolver},
+    db::{ImageRepository, PlanetRepository, UserRepository},
+    rate_limit::RateLimitConfig,
+    services::{
+        rate_limit_service::{RateLimitLayer, RateLimitService},
+        AppState,
+    },
+};
 
 const MONGODB_URI: &str = "MONGODB_URI";
 const REDIS_URI: &str = "REDIS_URI";
+const STORAGE: &str = "STORAGE";
+const CACHE: &str = "CACHE";
+const IMAGE_PLACEHOLDER: &str = "IMAGE_PLACEHOLDER";
+const CACHE_STALE_TTL: &str = "CACHE_STALE_TTL";
+const RATE_LIMIT: &str = "RATE_LIMIT";
+const RATE_LIMIT_ROUTES: &str = "RATE_LIMIT_ROUTES";
+const TRUSTED_PROXIES: &str = "TRUSTED_PROXIES";
+const TRUSTED_PROXY_HEADER: &str = "TRUSTED_PROXY_HEADER";
+const DEFAULT_TRUSTED_PROXY_HEADER: &str = "X-Forwarded-For";
+const DEFAULT_RATE_LIMIT: &str = "sliding_window:60/60";
+// images are expensive to resize, and writes are rare
+const DEFAULT_RATE_LIMIT_ROUTES: &str = "GET /planets=sliding_window:10/60;\
+    GET /planets/:planet/image=sliding_window:20/60;\
+    POST=sliding_window:10/60;PUT=sliding_window:10/60;\
+    PATCH=sliding_window:10/60;DELETE=sliding_window:10/60";
+const MAX_IMAGE_SIZE: usize = 10 * 1024 * 1024;
 
 #[tokio::main]
 async fn main() {
@@ -30,38 +65,71 @@ async fn main() {
 
     log::info!("Starting Solar system info server...");
 
-    let mongodb_uri = env::var(MONGODB_URI).expect(&format!("{} should be specified", MONGODB_URI));
-    let mongodb_client = db::MongoDbClient::new(mongodb_uri)
-        .await
-        .expect("Failed to create MongoDB client");
+    let (planet_repository, image_repository, user_repository) = create_repositories().await;
 
-    let redis_uri = env::var(REDIS_URI).expect(&format!("{} should be specified", REDIS_URI));
-    let redis_client =
-        redis::create_redis_client(redis_uri).expect("Failed to create Redis client");
-    let redis_connection_manager = redis_client
-        .get_connection_manager()
-        .await
-        .expect("Failed to create Redis connection manager");
+    let (cache, rate_limiter) = create_cache().await;
+
+    let image_placeholder = env::var(IMAGE_PLACEHOLDER).is_ok_and(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Failed to parse {} value into bool", value))
+    });
+
+    let stale_cache_ttl_secs = env::var(CACHE_STALE_TTL).ok().map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Failed to parse {} value into u64", value))
+    });
 
     let app_state = Arc::new(AppState::new(
-        mongodb_client,
-        redis_client,
-        redis_connection_manager,
+        planet_repository,
+        image_repository,
+        user_repository,
+        cache,
+        image_placeholder,
+        stale_cache_ttl_secs,
+        RateLimitService::new(rate_limiter, create_rate_limit_config()),
     ));
 
     let router = Router::new()
         .route("/", get(handlers::index))
+        .route("/events", get(handlers::events))
+        .route("/ws", get(handlers::ws))
         .route(
             "/planets",
             get(handlers::get_planets).post(handlers::create_planet),
         )
+        .route("/planets/import", post(handlers::import_planets))
+        .route("/planets/export", get(handlers::export_planets))
         .route(
             "/planets/:planet_id",
             get(handlers::get_planet)
                 .delete(handlers::delete_planet)
-                .put(handlers::update_planet),
+                .put(handlers::update_planet)
+                .patch(handlers::patch_planet),
+        )
+        .route(
+            "/planets/:planet/image",
+            get(handlers::get_image_of_planet)
+                .put(handlers::upload_image_of_planet)
+                .layer(DefaultBodyLimit::max(MAX_IMAGE_SIZE)),
+        )
+        .route(
+            "/planets/:planet_id/satellites",
+            get(handlers::get_satellites).post(handlers::create_satellite),
         )
-        .route("/planets/:planet/image", get(handlers::get_image_of_planet))
+        .route(
+            "/planets/:planet_id/satellites/:satellite_name",
+            get(handlers::get_satellite)
+                .put(handlers::update_satellite)
+                .delete(handlers::delete_satellite),
+        )
+        .route("/satellites", get(handlers::get_all_satellites))
+        .layer(RateLimitLayer::new(
+            app_state.rate_limit_service.clone(),
+            app_state.user_service.clone(),
+        ))
+        .layer(ClientIpLayer::new(create_client_ip_resolver()))
         .with_state(app_state)
         .into_make_service_with_connect_info::<SocketAddr>();
 
@@ -76,7 +144,7 @@ async fn main() {
             },
             |s| {
                 s.parse()
-                    .expect(&format!("Failed to parse {} value to Ipv4Addr", s))
+                    .unwrap_or_else(|_| panic!("Failed to parse {} value to Ipv4Addr", s))
             },
         )
         .into();
@@ -93,7 +161,7 @@ async fn main() {
         },
         |p| {
             p.parse()
-                .expect(&format!("Failed to parse {} value into u16", p))
+                .unwrap_or_else(|_| panic!("Failed to parse {} value into u16", p))
         },
     );
 
@@ -104,3 +172,95 @@ async fn main() {
 
     axum::serve(listener, router).await.unwrap();
 }
+
+async fn create_repositories() -> (
+    Arc<dyn PlanetRepository>,
+    Arc<dyn ImageRepository>,
+    Arc<dyn UserRepository>,
+) {
+    let storage = env::var(STORAGE).unwrap_or_else(|_| String::from("mongodb"));
+
+    match storage.as_str() {
+        "mongodb" => {
+            let mongodb_uri = env::var(MONGODB_URI)
+                .unwrap_or_else(|_| panic!("{} should be specified", MONGODB_URI));
+            let mongodb_client = Arc::new(
+                db::MongoDbClient::new(mongodb_uri)
+                    .await
+                    .expect("Failed to create MongoDB client"),
+            );
+            mongodb_client
+                .migrate()
+                .await
+                .expect("Failed to migrate MongoDB data");
+
+            (
+                mongodb_client.clone(),
+                mongodb_client.clone(),
+                mongodb_client,
+            )
+        }
+        "memory" => {
+            log::warn!("In-memory storage is used, all changes are lost on restart");
+
+            let in_memory_db = Arc::new(db::InMemoryDb::new());
+
+            (in_memory_db.clone(), in_memory_db.clone(), in_memory_db)
+        }
+        _ => panic!("Unknown {} value: {}", STORAGE, storage),
+    }
+}
+
+async fn create_cache() -> (Arc<dyn Cache>, Arc<dyn RateLimiter>) {
+    let cache = env::var(CACHE).unwrap_or_else(|_| String::from("redis"));
+
+    match cache.as_str() {
+        "redis" => {
+            let redis_uri =
+                env::var(REDIS_URI).unwrap_or_else(|_| panic!("{} should be specified", REDIS_URI));
+
+            let redis_cache = Arc::new(
+                cache::RedisCache::new(redis_uri)
+                    .await
+                    .expect("Failed to create Redis cache"),
+            );
+
+            (redis_cache.clone(), redis_cache)
+        }
+        "memory" => {
+            log::warn!("In-memory cache is used, it isn't shared between server instances");
+
+            let in_memory_cache = Arc::new(cache::InMemoryCache::new());
+
+            (in_memory_cache.clone(), in_memory_cache)
+        }
+        _ => panic!("Unknown {} value: {}", CACHE, cache),
+    }
+}
+
+fn create_rate_limit_config() -> RateLimitConfig {
+    let default_policy = env::var(RATE_LIMIT)
+        .unwrap_or_else(|_| String::from(DEFAULT_RATE_LIMIT))
+        .parse()
+        .unwrap_or_else(|err| panic!("Failed to parse {}: {}", RATE_LIMIT, err));
+
+    let route_policies = env::var(RATE_LIMIT_ROUTES).unwrap_or_default();
+
+    RateLimitConfig::new(default_policy)
+        .with_route_policies(DEFAULT_RATE_LIMIT_ROUTES)
+        .and_then(|config| config.with_route_policies(&route_policies))
+        .unwrap_or_else(|err| panic!("Failed to parse {}: {}", RATE_LIMIT_ROUTES, err))
+}
+
+fn create_client_ip_resolver() -> ClientIpResolver {
+    let header = env::var(TRUSTED_PROXY_HEADER)
+        .unwrap_or_else(|_| String::from(DEFAULT_TRUSTED_PROXY_HEADER))
+        .parse()
+        .unwrap_or_else(|err| panic!("Failed to parse {}: {}", TRUSTED_PROXY_HEADER, err));
+
+    let trusted_proxies = env::var(TRUSTED_PROXIES).unwrap_or_default();
+
+    ClientIpResolver::new(header)
+        .with_trusted_proxies(&trusted_proxies)
+        .unwrap_or_else(|err| panic!("Failed to parse {}: {}", TRUSTED_PROXIES, err))
+}
@@ -1,25 +1,38 @@
 use std::{fmt, str::FromStr};
 
-use chrono::Utc;
+use chrono::{DateTime, NaiveDate, Utc};
 use mongodb::bson::{self, oid::ObjectId, Document};
 use serde::{Deserialize, Serialize};
 
-use crate::dto::{PlanetDto, SatelliteDto};
+use crate::{
+    conditional,
+    dto::{PlanetDto, SatelliteDto},
+    error::CustomResult,
+};
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct User {
     pub password: String,
     pub access: AccessType,
+    #[serde(default)]
+    pub quota: Option<UserQuota>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+// limits of requests of a user, which replace the ones of routes
+#[derive(Clone, Debug, Deserialize)]
+pub struct UserQuota {
+    pub per_minute: Option<u64>,
+    pub per_day: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub enum AccessType {
     None,
     ReadOnly,
     ReadWrite,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Planet {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
@@ -27,6 +40,12 @@ pub struct Planet {
     pub r#type: PlanetType,
     pub mean_radius: f32,
     pub satellites: Option<Vec<Satellite>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<PlanetImage>,
+    #[serde(default)]
+    pub revision: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<bson::DateTime>,
 }
 
 impl From<PlanetDto> for Planet {
@@ -37,6 +56,7 @@ impl From<PlanetDto> for Planet {
             r#type,
             mean_radius,
             satellites,
+            image,
         }: PlanetDto,
     ) -> Self {
         let id = id.map(|id| ObjectId::from_str(id.as_str()).expect("Can't convert to ObjectId"));
@@ -49,17 +69,41 @@ impl From<PlanetDto> for Planet {
             r#type,
             mean_radius,
             satellites,
+            image,
+            revision: 0,
+            updated_at: None,
         }
     }
 }
 
+impl Planet {
+    // seeded planets have never been updated, so their creation time is used
+    pub fn last_modified(&self) -> Option<DateTime<Utc>> {
+        self.updated_at
+            .or_else(|| self.id.map(|id| id.timestamp()))
+            .and_then(|dt| DateTime::from_timestamp_millis(dt.timestamp_millis()))
+    }
+
+    // the revision is hashed as well, so a planet changed and changed back gets a new ETag
+    pub fn etag(&self) -> CustomResult<String> {
+        conditional::get_json_etag(&(self.revision, PlanetDto::from(self.clone())))
+    }
+
+    pub fn get_satellite(&self, satellite_name: &str) -> Option<&Satellite> {
+        self.satellites
+            .iter()
+            .flatten()
+            .find(|satellite| satellite.name == satellite_name)
+    }
+}
+
 impl From<&Planet> for Document {
     fn from(planet: &Planet) -> Self {
         bson::to_document(planet).expect("Can't convert planet to Document")
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
 pub enum PlanetType {
     TerrestrialPlanet,
     GasGiant,
@@ -73,7 +117,21 @@ impl fmt::Display for PlanetType {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl FromStr for PlanetType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "TerrestrialPlanet" => Ok(PlanetType::TerrestrialPlanet),
+            "GasGiant" => Ok(PlanetType::GasGiant),
+            "IceGiant" => Ok(PlanetType::IceGiant),
+            "DwarfPlanet" => Ok(PlanetType::DwarfPlanet),
+            _ => Err(format!("Unknown planet type: {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Satellite {
     pub name: String,
     pub first_spacecraft_landing_date: Option<mongodb::bson::DateTime>,
@@ -86,15 +144,7 @@ impl From<SatelliteDto> for Satellite {
             first_spacecraft_landing_date,
         }: SatelliteDto,
     ) -> Self {
-        let first_spacecraft_landing_date = first_spacecraft_landing_date.map(|nd| {
-            mongodb::bson::DateTime::from_millis(
-                chrono::DateTime::<Utc>::from_naive_utc_and_offset(
-                    nd.and_hms_opt(0, 0, 0).unwrap(),
-                    Utc,
-                )
-                .timestamp(),
-            )
-        });
+        let first_spacecraft_landing_date = first_spacecraft_landing_date.map(to_bson_date_time);
 
         Self {
             name,
@@ -102,3 +152,53 @@ impl From<SatelliteDto> for Satellite {
         }
     }
 }
+
+impl From<&Satellite> for Document {
+    fn from(satellite: &Satellite) -> Self {
+        bson::to_document(satellite).expect("Can't convert satellite to Document")
+    }
+}
+
+// an embedded image of a planet, which doesn't depend on the planet name
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PlanetImage {
+    pub key: String,
+    pub content_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credits: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+}
+
+// the ETag is a hash of the image content, so it's computed once on upload
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlanetImageInfo {
+    pub content_type: String,
+    pub etag: String,
+    pub uploaded_at: Option<bson::DateTime>,
+}
+
+impl PlanetImageInfo {
+    pub fn last_modified(&self) -> Option<DateTime<Utc>> {
+        self.uploaded_at
+            .and_then(|dt| DateTime::from_timestamp_millis(dt.timestamp_millis()))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlanetSatellite {
+    pub planet_id: ObjectId,
+    pub planet_name: String,
+    pub planet_type: PlanetType,
+    pub satellite: Satellite,
+}
+
+pub fn to_bson_date_time(date: NaiveDate) -> bson::DateTime {
+    let millis = date
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        .timestamp_millis();
+
+    bson::DateTime::from_millis(millis)
+}
@@ -0,0 +1,209 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::NaiveDate;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{CustomError, CustomResult},
+    model::PlanetType,
+};
+
+pub const DEFAULT_PAGE_LIMIT: u32 = 20;
+pub const MAX_PAGE_LIMIT: u32 = 100;
+
+#[derive(Clone, Default, Serialize, Debug)]
+pub struct PlanetFilter {
+    pub types: Vec<PlanetType>,
+    pub min_radius: Option<f64>,
+    pub max_radius: Option<f64>,
+    pub name_prefix: Option<String>,
+    pub name_contains: Option<String>,
+    pub has_satellites: Option<bool>,
+    pub satellite: Option<String>,
+}
+
+impl PlanetFilter {
+    pub fn validate(&self) -> CustomResult<()> {
+        for (field, radius) in [
+            ("min_radius", self.min_radius),
+            ("max_radius", self.max_radius),
+        ] {
+            if radius.is_some_and(|r| !r.is_finite() || r < 0.0) {
+                return Err(CustomError::InvalidQuery {
+                    field: field.to_string(),
+                    message: String::from("Radius should be a non-negative number"),
+                });
+            }
+        }
+
+        if let (Some(min_radius), Some(max_radius)) = (self.min_radius, self.max_radius) {
+            if min_radius > max_radius {
+                return Err(CustomError::InvalidQuery {
+                    field: String::from("min_radius"),
+                    message: format!(
+                        "min_radius ({}) is greater than max_radius ({})",
+                        min_radius, max_radius
+                    ),
+                });
+            }
+        }
+
+        for (field, value) in [
+            ("name_prefix", &self.name_prefix),
+            ("name_contains", &self.name_contains),
+            ("satellite", &self.satellite),
+        ] {
+            if value.as_ref().is_some_and(|v| v.trim().is_empty()) {
+                return Err(CustomError::InvalidQuery {
+                    field: field.to_string(),
+                    message: String::from("Value should not be empty"),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // equal filters become the same regardless of the order of types and the case of names
+    pub fn normalize(&self) -> Self {
+        let mut types = self.types.clone();
+        types.sort_by_key(|planet_type| planet_type.to_string());
+        types.dedup();
+
+        Self {
+            types,
+            name_prefix: self.name_prefix.as_ref().map(|v| v.to_lowercase()),
+            name_contains: self.name_contains.as_ref().map(|v| v.to_lowercase()),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct SatelliteFilter {
+    pub planet_types: Vec<PlanetType>,
+    pub landed_from: Option<NaiveDate>,
+    pub landed_to: Option<NaiveDate>,
+}
+
+impl SatelliteFilter {
+    pub fn validate(&self) -> CustomResult<()> {
+        if let (Some(landed_from), Some(landed_to)) = (self.landed_from, self.landed_to) {
+            if landed_from > landed_to {
+                return Err(CustomError::InvalidQuery {
+                    field: String::from("landed_from"),
+                    message: format!(
+                        "landed_from ({}) is later than landed_to ({})",
+                        landed_from, landed_to
+                    ),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum PlanetSort {
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "mean_radius")]
+    MeanRadius,
+    #[serde(rename = "-mean_radius")]
+    MeanRadiusDesc,
+}
+
+impl PlanetSort {
+    pub fn field(&self) -> &'static str {
+        match self {
+            PlanetSort::Name => "name",
+            PlanetSort::MeanRadius | PlanetSort::MeanRadiusDesc => "mean_radius",
+        }
+    }
+
+    pub fn is_descending(&self) -> bool {
+        matches!(self, PlanetSort::MeanRadiusDesc)
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum SortValue {
+    Number(f64),
+    Text(String),
+}
+
+// the (sort value, id) pair of the last planet of a page, so the next page doesn't depend
+// on that planet still existing
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PageCursor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<SortValue>,
+    pub id: ObjectId,
+}
+
+impl PageCursor {
+    pub fn encode(&self) -> CustomResult<String> {
+        Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(self)?))
+    }
+
+    fn decode(cursor: &str, sort: Option<PlanetSort>) -> CustomResult<Self> {
+        let cursor = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|cursor| serde_json::from_slice::<Self>(&cursor).ok())
+            .ok_or_else(|| CustomError::InvalidQuery {
+                field: String::from("after"),
+                message: String::from("Cursor is malformed"),
+            })?;
+
+        let matches_sort = matches!(
+            (sort, &cursor.value),
+            (Some(PlanetSort::Name), Some(SortValue::Text(_)))
+                | (
+                    Some(PlanetSort::MeanRadius | PlanetSort::MeanRadiusDesc),
+                    Some(SortValue::Number(_))
+                )
+                | (None, None)
+        );
+
+        if !matches_sort {
+            return Err(CustomError::InvalidQuery {
+                field: String::from("after"),
+                message: String::from("Cursor belongs to another sort"),
+            });
+        }
+
+        Ok(cursor)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PageRequest {
+    pub limit: u32,
+    pub after: Option<PageCursor>,
+    pub sort: Option<PlanetSort>,
+}
+
+impl PageRequest {
+    pub fn new(
+        limit: Option<u32>,
+        after: Option<&str>,
+        sort: Option<PlanetSort>,
+    ) -> CustomResult<Self> {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+
+        let after = after
+            .map(|after| PageCursor::decode(after, sort))
+            .transpose()?;
+
+        Ok(Self { limit, after, sort })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
@@ -0,0 +1,251 @@
+use std::{collections::HashMap, str::FromStr};
+
+use axum::http::{header, HeaderMap, HeaderValue, Method};
+
+const DEFAULT_POLICY_KEY: &str = "default";
+const RATE_LIMIT_LIMIT: &str = "RateLimit-Limit";
+const RATE_LIMIT_REMAINING: &str = "RateLimit-Remaining";
+const RATE_LIMIT_RESET: &str = "RateLimit-Reset";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RateLimitAlgorithm {
+    // counts requests made during the last period, so there are no bursts at its boundaries
+    SlidingWindow,
+    // refills the limit evenly during the period, so unused requests can be spent at once
+    TokenBucket,
+}
+
+impl FromStr for RateLimitAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sliding_window" => Ok(RateLimitAlgorithm::SlidingWindow),
+            "token_bucket" => Ok(RateLimitAlgorithm::TokenBucket),
+            _ => Err(format!("Unknown rate limit algorithm: {}", s)),
+        }
+    }
+}
+
+// `limit` requests per `period_secs`, e.g. "sliding_window:10/60"
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RateLimitPolicy {
+    pub algorithm: RateLimitAlgorithm,
+    pub limit: u64,
+    pub period_secs: u64,
+}
+
+impl RateLimitPolicy {
+    pub fn period_ms(&self) -> u64 {
+        self.period_secs * 1000
+    }
+}
+
+impl FromStr for RateLimitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let get_malformed = || {
+            format!(
+                "Rate limit policy should look like <algorithm>:<limit>/<period_secs>, but got {}",
+                s
+            )
+        };
+
+        let (algorithm, rate) = s.trim().split_once(':').ok_or_else(get_malformed)?;
+        let (limit, period_secs) = rate.split_once('/').ok_or_else(get_malformed)?;
+
+        let policy = Self {
+            algorithm: algorithm.parse()?,
+            limit: limit.parse().map_err(|_| get_malformed())?,
+            period_secs: period_secs.parse().map_err(|_| get_malformed())?,
+        };
+
+        if policy.limit == 0 || policy.period_secs == 0 {
+            return Err(format!("Rate limit and period should be positive: {}", s));
+        }
+
+        Ok(policy)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct RateLimitDecision {
+    pub is_allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    // until the whole limit is available again
+    pub reset_after_ms: u64,
+    // until the next request is allowed, zero if this one is
+    pub retry_after_ms: u64,
+}
+
+impl RateLimitDecision {
+    pub fn reset_after_secs(&self) -> u64 {
+        self.reset_after_ms.div_ceil(1000)
+    }
+
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after_ms.div_ceil(1000)
+    }
+
+    pub fn headers(&self) -> HeaderMap {
+        let retry_after_secs = (!self.is_allowed).then(|| self.retry_after_secs());
+
+        get_headers(
+            self.limit,
+            self.remaining,
+            self.reset_after_secs(),
+            retry_after_secs,
+        )
+    }
+}
+
+// `Retry-After` is sent only along with 429 Too Many Requests
+pub fn get_headers(
+    limit: u64,
+    remaining: u64,
+    reset_after_secs: u64,
+    retry_after_secs: Option<u64>,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    headers.insert(RATE_LIMIT_LIMIT, HeaderValue::from(limit));
+    headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from(remaining));
+    headers.insert(RATE_LIMIT_RESET, HeaderValue::from(reset_after_secs));
+
+    if let Some(retry_after_secs) = retry_after_secs {
+        headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
+    }
+
+    headers
+}
+
+// policies of routes are keyed by "<METHOD> <path>", "<path>" or "<METHOD>", which are
+// matched in this order, and the path is the same as in the router, e.g. "PUT /planets/:planet_id"
+#[derive(Clone, Debug)]
+pub struct RateLimitConfig {
+    default_policy: RateLimitPolicy,
+    route_policies: HashMap<String, RateLimitPolicy>,
+}
+
+impl RateLimitConfig {
+    pub fn new(default_policy: RateLimitPolicy) -> Self {
+        Self {
+            default_policy,
+            route_policies: HashMap::new(),
+        }
+    }
+
+    // e.g. "GET /planets=token_bucket:20/60;GET /planets/:planet/image=sliding_window:30/60",
+    // a policy of a route replaces the one it already has
+    pub fn with_route_policies(mut self, route_policies: &str) -> Result<Self, String> {
+        for route_policy in route_policies.split(';').filter(|r| !r.trim().is_empty()) {
+            let (route, policy) = route_policy.split_once('=').ok_or_else(|| {
+                format!(
+                    "Route policy should look like <route>=<policy>, but got {}",
+                    route_policy
+                )
+            })?;
+
+            self.route_policies
+                .insert(route.trim().to_string(), policy.parse()?);
+        }
+
+        Ok(self)
+    }
+
+    // requests share the limit with the others matching the same key
+    pub fn get_policy(&self, method: &Method, path: &str) -> (String, RateLimitPolicy) {
+        [
+            format!("{} {}", method, path),
+            path.to_string(),
+            method.to_string(),
+        ]
+        .into_iter()
+        .find_map(|key| {
+            let policy = *self.route_policies.get(&key)?;
+
+            Some((key, policy))
+        })
+        .unwrap_or_else(|| (String::from(DEFAULT_POLICY_KEY), self.default_policy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!(
+            " token_bucket:20/60 ".parse::<RateLimitPolicy>(),
+            Ok(RateLimitPolicy {
+                algorithm: RateLimitAlgorithm::TokenBucket,
+                limit: 20,
+                period_secs: 60,
+            })
+        );
+        assert_eq!(
+            "sliding_window:1/1".parse::<RateLimitPolicy>(),
+            Ok(RateLimitPolicy {
+                algorithm: RateLimitAlgorithm::SlidingWindow,
+                limit: 1,
+                period_secs: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_policies() {
+        for policy in [
+            "",
+            "sliding_window",
+            "sliding_window:",
+            "sliding_window:10",
+            "sliding_window:10/",
+            "sliding_window:/60",
+            "sliding_window:ten/60",
+            "sliding_window:10/a minute",
+            "sliding_window:-1/60",
+            "sliding_window:10/60/60",
+            "sliding_window:0/60",
+            "sliding_window:10/0",
+            "fixed_window:10/60",
+            "10/60",
+            ":10/60",
+        ] {
+            assert!(policy.parse::<RateLimitPolicy>().is_err(), "{}", policy);
+        }
+    }
+
+    #[test]
+    fn matches_route_policies() {
+        let config = RateLimitConfig::new("sliding_window:60/60".parse().unwrap())
+            .with_route_policies(
+                "GET /planets=token_bucket:20/60; /planets=sliding_window:30/60;POST=sliding_window:5/60;",
+            )
+            .unwrap();
+
+        let get_key = |method: Method, path: &str| config.get_policy(&method, path).0;
+        assert_eq!(get_key(Method::GET, "/planets"), "GET /planets");
+        assert_eq!(get_key(Method::POST, "/planets"), "/planets");
+        assert_eq!(get_key(Method::POST, "/planets/import"), "POST");
+        assert_eq!(get_key(Method::GET, "/satellites"), DEFAULT_POLICY_KEY);
+
+        for route_policies in [
+            "GET /planets",
+            "GET /planets=",
+            "GET /planets=token_bucket:20",
+            "GET /planets=token_bucket:20/60;POST=5/60",
+        ] {
+            assert!(
+                RateLimitConfig::new("sliding_window:60/60".parse().unwrap())
+                    .with_route_policies(route_policies)
+                    .is_err(),
+                "{}",
+                route_policies
+            );
+        }
+    }
+}
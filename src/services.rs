@@ -7,10 +7,12 @@ use std::sync::Arc;
 
 use axum::extract::FromRef;
 use planet_service::PlanetService;
-use redis::{aio::ConnectionManager, Client};
 use user_service::UserService;
 
-use crate::db::MongoDbClient;
+use crate::{
+    cache::Cache,
+    db::{ImageRepository, PlanetRepository, UserRepository},
+};
 
 use rate_limit_service::RateLimitService;
 
@@ -22,19 +24,23 @@ pub struct AppState {
 
 impl AppState {
     pub fn new(
-        mongodb_client: MongoDbClient,
-        redis_client: Client,
-        redis_connection_manager: ConnectionManager,
+        planet_repository: Arc<dyn PlanetRepository>,
+        image_repository: Arc<dyn ImageRepository>,
+        user_repository: Arc<dyn UserRepository>,
+        cache: Arc<dyn Cache>,
+        image_placeholder: bool,
+        stale_cache_ttl_secs: Option<u64>,
+        rate_limit_service: RateLimitService,
     ) -> Self {
         let planet_service = PlanetService::new(
-            mongodb_client.clone(),
-            redis_client,
-            redis_connection_manager.clone(),
+            planet_repository,
+            image_repository,
+            cache.clone(),
+            image_placeholder,
+            stale_cache_ttl_secs,
         );
 
-        let user_service = UserService::new(mongodb_client);
-
-        let rate_limit_service = RateLimitService::new(redis_connection_manager);
+        let user_service = UserService::new(user_repository);
 
         Self {
             planet_service,
@@ -49,9 +55,3 @@ impl FromRef<Arc<AppState>> for UserService {
         input.user_service.clone()
     }
 }
-
-impl FromRef<Arc<AppState>> for RateLimitService {
-    fn from_ref(input: &Arc<AppState>) -> Self {
-        input.rate_limit_service.clone()
-    }
-}
@@ -1,14 +1,13 @@
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use axum::{
     async_trait,
     extract::{FromRef, FromRequestParts},
     http::request::Parts,
 };
-use http_auth_basic::{AuthBasicError, Credentials};
+use http_auth_basic::AuthBasicError;
 
 use crate::{error::CustomError, model::AccessType};
 
-use super::user_service::UserService;
+use super::user_service::{AuthenticatedUser, UserService};
 
 pub struct BasicAuth;
 
@@ -21,26 +20,22 @@ where
     type Rejection = CustomError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        // parse header
-        let Credentials {
-            user_id: username,
-            password,
-        } = parts
-            .headers
-            .get("Authorization")
-            .and_then(|header| header.to_str().ok())
-            .map_or_else(
-                || Err(AuthBasicError::InvalidAuthorizationHeader),
-                |auth_header| Credentials::from_header(auth_header.to_string()),
-            )?;
-
-        // get user
-        let user = UserService::from_ref(state).get_user(username).await?;
-
-        // verify password
-        PasswordHash::new(&user.password).and_then(|parsed_hash| {
-            Argon2::default().verify_password(password.as_bytes(), &parsed_hash)
-        })?;
+        // credentials may have been verified already, e.g. by the rate limiter
+        let user = match parts.extensions.get::<AuthenticatedUser>() {
+            Some(authenticated_user) => authenticated_user.user.clone(),
+            None => {
+                let auth_header = parts
+                    .headers
+                    .get("Authorization")
+                    .and_then(|header| header.to_str().ok())
+                    .ok_or(AuthBasicError::InvalidAuthorizationHeader)?;
+
+                UserService::from_ref(state)
+                    .authenticate(auth_header)
+                    .await?
+                    .user
+            }
+        };
 
         // check access
         let (got_access, required_access) = (&user.access, &AccessType::ReadWrite);
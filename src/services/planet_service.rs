@@ -1,35 +1,72 @@
-use std::str::FromStr;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+};
 
-use mongodb::bson::oid::ObjectId;
-use redis::{aio::ConnectionManager, AsyncCommands, Client, Value};
+use futures::Stream;
+use mongodb::bson::{oid::ObjectId, DateTime, Document};
+use serde_json::Value as JsonValue;
 
 use crate::{
-    db::MongoDbClient,
-    dto::PlanetMessage,
+    cache::{Cache, SingleFlight},
+    conditional,
+    db::{self, ImageRepository, PlanetRepository},
+    dto::{PlanetDto, PlanetEvent},
     error::{CustomError, CustomResult},
-    model::{Planet, PlanetType},
+    image_processing::{self, ImageVariant},
+    model::{Planet, PlanetImageInfo, PlanetSatellite, PlanetType, Satellite},
+    query::{Page, PageRequest, PlanetFilter, SatelliteFilter},
 };
 
 const PLANET_KEY_PREFIX: &str = "planet";
+const PLANETS_KEY_PREFIX: &str = "planets";
+const PLANETS_GENERATION_KEY: &str = "planets_generation";
 const PLANET_IMAGE_KEY_PREFIX: &str = "image";
-const NEW_PLANETS_CHANNEL_NAME: &str = "new_planets";
+const PLANET_EVENTS_STREAM_KEY: &str = "planet_events";
+const PLANET_EVENTS_STREAM_MAX_LEN: usize = 10_000;
+const PLANET_CACHE_TTL_SECS: u64 = 60;
+// should outlive cached pages, otherwise a reset generation could match stale ones
+const PLANETS_GENERATION_TTL_SECS: u64 = 24 * 60 * 60;
+// variants are cached by the ETag of the image, so they are never stale
+const PLANET_IMAGE_VARIANT_CACHE_TTL_SECS: u64 = 60 * 60;
+const IMAGE_WEBP: &str = "image/webp";
+// fields of `PlanetDto` which a client can change, the others are managed by the server
+const PATCHABLE_FIELDS: [&str; 5] = ["name", "type", "mean_radius", "satellites", "image"];
+
+enum PlanetImageLookup {
+    Uploaded(PlanetImageInfo),
+    Embedded(PlanetImageInfo),
+    // generated images aren't stored, so the content is returned along with the info
+    Placeholder(PlanetImageInfo, Vec<u8>),
+    Missing,
+}
 
 pub struct PlanetService {
-    mongodb_client: MongoDbClient,
-    redis_client: Client,
-    redis_connection_manager: ConnectionManager,
+    planet_repository: Arc<dyn PlanetRepository>,
+    image_repository: Arc<dyn ImageRepository>,
+    cache: Arc<dyn Cache>,
+    single_flight: SingleFlight,
+    image_placeholder: bool,
+    // placeholders depend only on the planet type, so each one is generated once
+    placeholders: Mutex<HashMap<PlanetType, (PlanetImageInfo, Vec<u8>)>>,
 }
 
 impl PlanetService {
     pub fn new(
-        mongodb_client: MongoDbClient,
-        redis_client: Client,
-        redis_connection_manager: ConnectionManager,
+        planet_repository: Arc<dyn PlanetRepository>,
+        image_repository: Arc<dyn ImageRepository>,
+        cache: Arc<dyn Cache>,
+        image_placeholder: bool,
+        stale_cache_ttl_secs: Option<u64>,
     ) -> Self {
         Self {
-            mongodb_client,
-            redis_client,
-            redis_connection_manager,
+            planet_repository,
+            image_repository,
+            single_flight: SingleFlight::new(cache.clone(), stale_cache_ttl_secs),
+            cache,
+            image_placeholder,
+            placeholders: Mutex::new(HashMap::new()),
         }
     }
 
@@ -44,108 +81,711 @@ impl PlanetService {
         )
     }
 
-    pub async fn get_planets(&self, planet_type: Option<PlanetType>) -> CustomResult<Vec<Planet>> {
-        self.mongodb_client.get_planets(planet_type).await
+    fn get_planet_image_variant_cache_key(
+        &self,
+        planet_id: &str,
+        image_info: &PlanetImageInfo,
+        variant: &ImageVariant,
+    ) -> String {
+        format!(
+            "{}:{}:{}",
+            self.get_planet_image_cache_key(planet_id),
+            image_info.etag.trim_matches('"'),
+            variant.key()
+        )
+    }
+
+    // pages are cached under the current generation, so a change of any planet
+    // invalidates all of them at once by incrementing it
+    async fn get_planets_cache_key(
+        &self,
+        planet_filter: &PlanetFilter,
+        page_request: &PageRequest,
+    ) -> CustomResult<String> {
+        let generation = self
+            .cache
+            .get(PLANETS_GENERATION_KEY)
+            .await?
+            .and_then(|value| String::from_utf8_lossy(&value).parse::<u64>().ok())
+            .unwrap_or_default();
+
+        let query_hash = conditional::get_json_etag(&(planet_filter.normalize(), page_request))?;
+
+        Ok(format!(
+            "{}:{}:{}",
+            PLANETS_KEY_PREFIX,
+            generation,
+            query_hash.trim_matches('"')
+        ))
+    }
+
+    pub async fn get_planets(
+        &self,
+        planet_filter: &PlanetFilter,
+        page_request: &PageRequest,
+    ) -> CustomResult<Page<Planet>> {
+        let cache_key = self
+            .get_planets_cache_key(planet_filter, page_request)
+            .await?;
+
+        let data = self
+            .single_flight
+            .get_or_load(&cache_key, PLANET_CACHE_TTL_SECS, || async {
+                log::debug!("No cached value - getting planets from db");
+
+                let page = self
+                    .planet_repository
+                    .get_planets(planet_filter, page_request)
+                    .await?;
+
+                Ok(serde_json::to_vec(&page)?)
+            })
+            .await?;
+
+        let page = serde_json::from_slice(&data)?;
+
+        Ok(page)
     }
 
     pub async fn get_planet(&self, planet_id: &str) -> CustomResult<Planet> {
         let cache_key = self.get_planet_cache_key(planet_id);
 
-        let mut con = self.redis_client.get_async_connection().await?;
-
-        match con.get(&cache_key).await? {
-            Value::Nil => {
+        let data = self
+            .single_flight
+            .get_or_load(&cache_key, PLANET_CACHE_TTL_SECS, || async {
                 log::debug!("No cached value - getting planet from db");
 
                 let planet = self
-                    .mongodb_client
+                    .planet_repository
                     .get_planet(ObjectId::from_str(planet_id)?)
                     .await?;
 
-                let _ = redis::pipe()
-                    .atomic()
-                    .set(&cache_key, &planet)
-                    .expire(&cache_key, 60)
-                    .query_async(&mut con)
-                    .await?;
+                Ok(serde_json::to_vec(&planet)?)
+            })
+            .await?;
 
-                Ok(planet)
-            }
-            Value::Data(data) => {
-                log::debug!("Return cached planet");
+        let planet = serde_json::from_slice(&data)?;
 
-                let planet = serde_json::from_slice(&data)?;
+        Ok(planet)
+    }
 
-                Ok(planet)
-            }
-            res => Err(CustomError::RedisError {
-                message: format!("Unexpected response from Redis: {:?}", res),
-            }),
-        }
+    async fn publish_planet_event(&self, planet_event: PlanetEvent) -> CustomResult<()> {
+        self.cache
+            .publish(
+                PLANET_EVENTS_STREAM_KEY,
+                serde_json::to_string(&planet_event)?,
+                PLANET_EVENTS_STREAM_MAX_LEN,
+            )
+            .await
     }
 
     pub async fn create_planet(&self, planet: Planet) -> CustomResult<Planet> {
-        let planet = self.mongodb_client.create_planet(planet).await?;
+        let planet = self.planet_repository.create_planet(planet).await?;
 
-        let planet_message = PlanetMessage::from(&planet);
-        self.redis_connection_manager
-            .clone()
-            .publish(
-                NEW_PLANETS_CHANNEL_NAME,
-                serde_json::to_string(&planet_message)?,
-            )
+        self.invalidate_planets().await?;
+
+        self.publish_planet_event(PlanetEvent::created(&planet))
             .await?;
 
         Ok(planet)
     }
 
-    pub async fn update_planet(&self, planet_id: &str, planet: Planet) -> CustomResult<Planet> {
-        let planet = {
+    pub async fn update_planet(
+        &self,
+        planet_id: &str,
+        planet: Planet,
+        if_match: Option<&[String]>,
+    ) -> CustomResult<Planet> {
+        let (old_planet, planet) = {
             let planet_id = ObjectId::from_str(planet_id)?;
-            self.mongodb_client.update_planet(planet_id, planet).await?
+            let old_planet = self.planet_repository.get_planet(planet_id).await?;
+            assert_etag_matches(&old_planet, if_match)?;
+
+            let planet = self
+                .planet_repository
+                .update_planet(planet_id, planet, old_planet.revision)
+                .await
+                .map_err(|err| to_concurrent_modification_error(err, if_match))?;
+
+            (old_planet, planet)
         };
 
-        let cache_key = self.get_planet_cache_key(planet_id);
-        self.redis_connection_manager.clone().del(cache_key).await?;
+        self.invalidate_planet(planet_id).await?;
+
+        self.publish_planet_event(PlanetEvent::updated(&old_planet, &planet))
+            .await?;
 
         Ok(planet)
     }
 
-    pub async fn delete_planet(&self, planet_id: &str) -> CustomResult<()> {
+    pub async fn patch_planet(
+        &self,
+        planet_id: &str,
+        patch: JsonValue,
+        if_match: Option<&[String]>,
+    ) -> CustomResult<Planet> {
+        let JsonValue::Object(patch) = patch else {
+            return Err(CustomError::BadRequest {
+                message: String::from("Merge patch should be a JSON object"),
+            });
+        };
+
+        if let Some(field) = patch
+            .keys()
+            .find(|field| !PATCHABLE_FIELDS.contains(&field.as_str()))
         {
-            let planet_id = ObjectId::from_str(planet_id)?;
-            self.mongodb_client.delete_planet(planet_id).await?;
+            return Err(CustomError::BadRequest {
+                message: format!("Field {} can't be patched", field),
+            });
         }
 
+        let (old_planet, planet) = {
+            let planet_id = ObjectId::from_str(planet_id)?;
+            let old_planet = self.planet_repository.get_planet(planet_id).await?;
+            assert_etag_matches(&old_planet, if_match)?;
+
+            let mut target = serde_json::to_value(PlanetDto::from(old_planet.clone()))?;
+            apply_merge_patch(&mut target, &JsonValue::Object(patch.clone()));
+
+            let patched_planet = serde_json::from_value::<PlanetDto>(target)
+                .map(Planet::from)
+                .map_err(|err| CustomError::BadRequest {
+                    message: format!("Patched planet is invalid: {}", err),
+                })?;
+            let patched_document = Document::from(&patched_planet);
+
+            // only the fields provided by the patch are changed
+            let (mut set, mut unset) = (Document::new(), Document::new());
+            for (field, value) in patch {
+                match patched_document.get(&field) {
+                    Some(patched_value) if !value.is_null() => {
+                        set.insert(field, patched_value.clone());
+                    }
+                    _ => {
+                        unset.insert(field, "");
+                    }
+                }
+            }
+
+            let planet = self
+                .planet_repository
+                .patch_planet(planet_id, set, unset, old_planet.revision)
+                .await
+                .map_err(|err| to_concurrent_modification_error(err, if_match))?;
+
+            (old_planet, planet)
+        };
+
+        self.invalidate_planet(planet_id).await?;
+
+        self.publish_planet_event(PlanetEvent::updated(&old_planet, &planet))
+            .await?;
+
+        Ok(planet)
+    }
+
+    pub async fn delete_planet(
+        &self,
+        planet_id: &str,
+        if_match: Option<&[String]>,
+    ) -> CustomResult<()> {
+        let planet = {
+            let planet_id = ObjectId::from_str(planet_id)?;
+            let planet = self.planet_repository.get_planet(planet_id).await?;
+            assert_etag_matches(&planet, if_match)?;
+
+            let planet = self
+                .planet_repository
+                .delete_planet(planet_id, planet.revision)
+                .await
+                .map_err(|err| to_concurrent_modification_error(err, if_match))?;
+
+            self.image_repository.delete_planet_image(planet_id).await?;
+
+            planet
+        };
+
+        self.invalidate_planet(planet_id).await?;
+
+        self.publish_planet_event(PlanetEvent::deleted(&planet))
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn export_planets(
+        &self,
+    ) -> CustomResult<impl Stream<Item = CustomResult<Planet>> + Send + 'static> {
+        self.planet_repository.get_all_planets().await
+    }
+
+    // returns the imported planet, and whether it has been created rather than updated
+    pub async fn import_planet(&self, mut planet: Planet) -> CustomResult<(Planet, bool)> {
+        // imported planets are matched by name
+        planet.id = None;
+
+        let (planet, old_planet) = self.planet_repository.upsert_planet_by_name(planet).await?;
+
+        let planet_event = match &old_planet {
+            Some(old_planet) => {
+                if let Some(planet_id) = planet.id {
+                    self.invalidate_planet(&planet_id.to_string()).await?;
+                }
+
+                PlanetEvent::updated(old_planet, &planet)
+            }
+            None => {
+                self.invalidate_planets().await?;
+
+                PlanetEvent::created(&planet)
+            }
+        };
+
+        self.publish_planet_event(planet_event).await?;
+
+        Ok((planet, old_planet.is_none()))
+    }
+
+    // any change of a planet may affect its image and pages of planets as well
+    async fn invalidate_planet(&self, planet_id: &str) -> CustomResult<()> {
         let cache_key = self.get_planet_cache_key(planet_id);
-        self.redis_connection_manager.clone().del(cache_key).await?;
+
+        self.single_flight.invalidate(&cache_key).await?;
+
+        self.invalidate_planet_image(planet_id).await?;
+
+        self.invalidate_planets().await
+    }
+
+    async fn invalidate_planets(&self) -> CustomResult<()> {
+        self.cache
+            .incr(PLANETS_GENERATION_KEY, PLANETS_GENERATION_TTL_SECS)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_satellites(&self, planet_id: &str) -> CustomResult<Vec<Satellite>> {
+        let planet = self.get_planet(planet_id).await?;
+
+        Ok(planet.satellites.unwrap_or_default())
+    }
+
+    pub async fn get_satellite(
+        &self,
+        planet_id: &str,
+        satellite_name: &str,
+    ) -> CustomResult<Satellite> {
+        self.get_planet(planet_id)
+            .await?
+            .get_satellite(satellite_name)
+            .cloned()
+            .ok_or(CustomError::NotFound {
+                message: format!("Can't find a satellite by name: {}", satellite_name),
+            })
+    }
+
+    pub async fn get_all_satellites(
+        &self,
+        satellite_filter: &SatelliteFilter,
+    ) -> CustomResult<Vec<PlanetSatellite>> {
+        self.planet_repository
+            .get_all_satellites(satellite_filter)
+            .await
+    }
+
+    pub async fn create_satellite(
+        &self,
+        planet_id: &str,
+        satellite: Satellite,
+    ) -> CustomResult<Satellite> {
+        let satellite_name = satellite.name.clone();
+
+        let (old_planet, planet) = {
+            let planet_id = ObjectId::from_str(planet_id)?;
+            let old_planet = self.planet_repository.get_planet(planet_id).await?;
+            let planet = self
+                .planet_repository
+                .create_satellite(planet_id, satellite, old_planet.revision)
+                .await
+                .map_err(|err| to_concurrent_modification_error(err, None))?;
+
+            (old_planet, planet)
+        };
+
+        self.invalidate_planet(planet_id).await?;
+
+        self.publish_planet_event(PlanetEvent::updated(&old_planet, &planet))
+            .await?;
+
+        planet
+            .get_satellite(&satellite_name)
+            .cloned()
+            .ok_or(CustomError::NotFound {
+                message: format!("Can't find created satellite: {}", satellite_name),
+            })
+    }
+
+    pub async fn update_satellite(
+        &self,
+        planet_id: &str,
+        satellite_name: &str,
+        satellite: Satellite,
+    ) -> CustomResult<Satellite> {
+        let new_satellite_name = satellite.name.clone();
+
+        let (old_planet, planet) = {
+            let planet_id = ObjectId::from_str(planet_id)?;
+            let old_planet = self.planet_repository.get_planet(planet_id).await?;
+            let planet = self
+                .planet_repository
+                .update_satellite(planet_id, satellite_name, satellite, old_planet.revision)
+                .await
+                .map_err(|err| to_concurrent_modification_error(err, None))?;
+
+            (old_planet, planet)
+        };
+
+        self.invalidate_planet(planet_id).await?;
+
+        self.publish_planet_event(PlanetEvent::updated(&old_planet, &planet))
+            .await?;
+
+        planet
+            .get_satellite(&new_satellite_name)
+            .cloned()
+            .ok_or(CustomError::NotFound {
+                message: format!("Can't find updated satellite: {}", new_satellite_name),
+            })
+    }
+
+    pub async fn delete_satellite(
+        &self,
+        planet_id: &str,
+        satellite_name: &str,
+    ) -> CustomResult<()> {
+        let (old_planet, planet) = {
+            let planet_id = ObjectId::from_str(planet_id)?;
+            let old_planet = self.planet_repository.get_planet(planet_id).await?;
+            let planet = self
+                .planet_repository
+                .delete_satellite(planet_id, satellite_name, old_planet.revision)
+                .await
+                .map_err(|err| to_concurrent_modification_error(err, None))?;
+
+            (old_planet, planet)
+        };
+
+        self.invalidate_planet(planet_id).await?;
+
+        self.publish_planet_event(PlanetEvent::updated(&old_planet, &planet))
+            .await?;
 
         Ok(())
     }
 
+    // uploaded images take precedence over the embedded ones
+    async fn lookup_planet_image(&self, planet: &Planet) -> CustomResult<PlanetImageLookup> {
+        let planet_id = planet.id.unwrap_or_default();
+
+        if let Some(image_info) = self
+            .image_repository
+            .get_planet_image_info(planet_id)
+            .await?
+        {
+            return Ok(PlanetImageLookup::Uploaded(image_info));
+        }
+
+        if let Some(image_info) = planet.image.as_ref().and_then(db::get_planet_image_info) {
+            return Ok(PlanetImageLookup::Embedded(image_info));
+        }
+
+        if self.image_placeholder {
+            let (image_info, image) = self.get_placeholder(planet.r#type).await?;
+
+            return Ok(PlanetImageLookup::Placeholder(image_info, image));
+        }
+
+        Ok(PlanetImageLookup::Missing)
+    }
+
+    async fn get_placeholder(
+        &self,
+        planet_type: PlanetType,
+    ) -> CustomResult<(PlanetImageInfo, Vec<u8>)> {
+        if let Some(placeholder) = self.lock_placeholders().get(&planet_type) {
+            return Ok(placeholder.clone());
+        }
+
+        let image = tokio::task::spawn_blocking(move || {
+            image_processing::generate_placeholder(planet_type)
+        })
+        .await??;
+
+        let image_info = PlanetImageInfo {
+            content_type: mime::IMAGE_PNG.to_string(),
+            etag: conditional::get_strong_etag(&image),
+            uploaded_at: None,
+        };
+
+        // concurrent requests may generate the same placeholder, which is harmless
+        self.lock_placeholders()
+            .insert(planet_type, (image_info.clone(), image.clone()));
+
+        Ok((image_info, image))
+    }
+
+    fn lock_placeholders(&self) -> MutexGuard<'_, HashMap<PlanetType, (PlanetImageInfo, Vec<u8>)>> {
+        self.placeholders
+            .lock()
+            .expect("Placeholders lock is poisoned")
+    }
+
+    pub async fn get_planet_image_info(&self, planet_id: &str) -> CustomResult<PlanetImageInfo> {
+        let planet = self.get_planet(planet_id).await?;
+
+        match self.lookup_planet_image(&planet).await? {
+            PlanetImageLookup::Uploaded(image_info)
+            | PlanetImageLookup::Embedded(image_info)
+            | PlanetImageLookup::Placeholder(image_info, _) => Ok(image_info),
+            PlanetImageLookup::Missing => Err(get_image_not_found(&planet.name)),
+        }
+    }
+
     pub async fn get_planet_image(&self, planet_id: &str) -> CustomResult<Vec<u8>> {
         let cache_key = self.get_planet_image_cache_key(planet_id);
 
-        let mut con = self.redis_client.get_async_connection().await?;
+        self.single_flight
+            .get_or_load(&cache_key, PLANET_CACHE_TTL_SECS, || async {
+                let planet = self
+                    .planet_repository
+                    .get_planet(ObjectId::from_str(planet_id)?)
+                    .await?;
 
-        match con.get(&cache_key).await? {
-            Value::Nil => {
-                let planet_id = ObjectId::from_str(planet_id)?;
-                let planet = self.mongodb_client.get_planet(planet_id).await?;
-                let result = crate::db::get_planet_image(&planet.name);
+                match self.lookup_planet_image(&planet).await? {
+                    PlanetImageLookup::Uploaded(_) => {
+                        self.image_repository
+                            .get_planet_image(planet.id.unwrap_or_default())
+                            .await?
+                    }
+                    PlanetImageLookup::Embedded(_) => {
+                        planet.image.as_ref().and_then(db::get_planet_image)
+                    }
+                    PlanetImageLookup::Placeholder(_, image) => Some(image),
+                    PlanetImageLookup::Missing => None,
+                }
+                .ok_or_else(|| get_image_not_found(&planet.name))
+            })
+            .await
+    }
 
-                let _ = redis::pipe()
-                    .set(&cache_key, result.clone())
-                    .expire(&cache_key, 60)
-                    .query_async(&mut con)
-                    .await?;
+    pub async fn get_planet_image_variant(
+        &self,
+        planet_id: &str,
+        image_info: &PlanetImageInfo,
+        variant: ImageVariant,
+    ) -> CustomResult<Vec<u8>> {
+        if variant.is_original(image_info) {
+            return self.get_planet_image(planet_id).await;
+        }
+
+        let cache_key = self.get_planet_image_variant_cache_key(planet_id, image_info, &variant);
+
+        self.single_flight
+            .get_or_load(&cache_key, PLANET_IMAGE_VARIANT_CACHE_TTL_SECS, || async {
+                let image = self.get_planet_image(planet_id).await?;
+
+                // decoding and encoding are CPU-bound, so they shouldn't block the runtime
+                let result = tokio::task::spawn_blocking(move || variant.apply(&image)).await??;
 
-                return Ok(result);
+                Ok(result)
+            })
+            .await
+    }
+
+    pub async fn upload_planet_image(
+        &self,
+        planet_id: &str,
+        content_type: Option<&str>,
+        image: Vec<u8>,
+    ) -> CustomResult<PlanetImageInfo> {
+        let planet_id = ObjectId::from_str(planet_id)?;
+        self.planet_repository.get_planet(planet_id).await?;
+
+        let detected_content_type =
+            get_image_content_type(&image).ok_or(CustomError::UnsupportedMediaType {
+                message: String::from("Image should be a JPEG, PNG, GIF or WebP"),
+            })?;
+
+        if let Some(content_type) = content_type
+            .filter(|content_type| *content_type != mime::APPLICATION_OCTET_STREAM.as_ref())
+        {
+            if content_type != detected_content_type {
+                return Err(CustomError::UnsupportedMediaType {
+                    message: format!(
+                        "Content-Type {} doesn't match the image: {}",
+                        content_type, detected_content_type
+                    ),
+                });
             }
-            Value::Data(value) => Ok(value),
-            res => Err(CustomError::RedisError {
-                message: format!("Unexpected response from Redis: {:?}", res),
-            }),
         }
+
+        let image_info = PlanetImageInfo {
+            content_type: detected_content_type.to_string(),
+            etag: conditional::get_strong_etag(&image),
+            uploaded_at: Some(DateTime::now()),
+        };
+
+        self.image_repository
+            .put_planet_image(planet_id, image_info.clone(), image)
+            .await?;
+
+        self.invalidate_planet_image(&planet_id.to_hex()).await?;
+
+        Ok(image_info)
+    }
+
+    // variants are cached by the ETag of the image, so a changed image doesn't reuse them
+    async fn invalidate_planet_image(&self, planet_id: &str) -> CustomResult<()> {
+        let cache_key = self.get_planet_image_cache_key(planet_id);
+
+        self.single_flight.invalidate(&cache_key).await
+    }
+
+    // events after `last_event_id` are replayed first, otherwise only new ones are returned
+    pub async fn subscribe_to_planet_events(
+        &self,
+        last_event_id: Option<String>,
+    ) -> CustomResult<impl Stream<Item = (String, String)>> {
+        self.cache
+            .subscribe(PLANET_EVENTS_STREAM_KEY, last_event_id)
+            .await
+    }
+}
+
+fn get_image_not_found(planet_name: &str) -> CustomError {
+    CustomError::ImageNotFound {
+        message: format!("There is no image of planet: {}", planet_name),
+    }
+}
+
+// the image format is detected by its signature, so a wrong Content-Type can't be stored
+fn get_image_content_type(image: &[u8]) -> Option<&'static str> {
+    match image {
+        [0xFF, 0xD8, 0xFF, ..] => Some(mime::IMAGE_JPEG.as_ref()),
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(mime::IMAGE_PNG.as_ref()),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(mime::IMAGE_GIF.as_ref()),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(IMAGE_WEBP),
+        _ => None,
+    }
+}
+
+fn assert_etag_matches(planet: &Planet, if_match: Option<&[String]>) -> CustomResult<()> {
+    let Some(etags) = if_match else {
+        return Ok(());
+    };
+
+    let etag = planet.etag()?;
+
+    if !etags.contains(&etag) {
+        return Err(CustomError::PreconditionFailed {
+            message: format!("Planet ETag {} doesn't match If-Match: {:?}", etag, etags),
+        });
+    }
+
+    Ok(())
+}
+
+// a planet changed between reading and writing is a conflict unless the client set a precondition
+fn to_concurrent_modification_error(err: CustomError, if_match: Option<&[String]>) -> CustomError {
+    match (err, if_match) {
+        (CustomError::PreconditionFailed { message }, None) => CustomError::Conflict { message },
+        (err, _) => err,
+    }
+}
+
+// see RFC 7396
+fn apply_merge_patch(target: &mut JsonValue, patch: &JsonValue) {
+    let JsonValue::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_object() {
+        *target = JsonValue::Object(Default::default());
+    }
+
+    if let JsonValue::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                apply_merge_patch(target.entry(key).or_insert(JsonValue::Null), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn merge(mut target: JsonValue, patch: JsonValue) -> JsonValue {
+        apply_merge_patch(&mut target, &patch);
+
+        target
+    }
+
+    #[test]
+    fn removes_fields_set_to_null() {
+        assert_eq!(
+            merge(
+                json!({"name": "Mars", "satellites": [{"name": "Phobos"}]}),
+                json!({"satellites": null, "absent": null})
+            ),
+            json!({"name": "Mars"})
+        );
+    }
+
+    #[test]
+    fn merges_nested_objects() {
+        assert_eq!(
+            merge(
+                json!({"name": "Mars", "image": {"key": "mars.jpg", "format": "jpg"}}),
+                json!({"image": {"key": "mars.png", "format": null, "size": 1}})
+            ),
+            json!({"name": "Mars", "image": {"key": "mars.png", "size": 1}})
+        );
+
+        // a non-object is replaced by an object, dropping nulls of the patch
+        assert_eq!(
+            merge(
+                json!({"image": "mars.jpg"}),
+                json!({"image": {"key": null, "a": 1}})
+            ),
+            json!({"image": {"a": 1}})
+        );
+    }
+
+    #[test]
+    fn replaces_arrays_and_scalars() {
+        assert_eq!(
+            merge(
+                json!({"satellites": [{"name": "Phobos"}, {"name": "Deimos"}], "mean_radius": 1}),
+                json!({"satellites": [{"name": "Deimos"}], "mean_radius": 2})
+            ),
+            json!({"satellites": [{"name": "Deimos"}], "mean_radius": 2})
+        );
+
+        // a patch which isn't an object replaces the whole target
+        assert_eq!(
+            merge(json!({"name": "Mars"}), json!(["Mars"])),
+            json!(["Mars"])
+        );
+        assert_eq!(
+            merge(json!({"name": "Mars"}), json!({})),
+            json!({"name": "Mars"})
+        );
     }
 }
@@ -1,65 +1,317 @@
-use std::net::SocketAddr;
+use std::{
+    convert::Infallible,
+    mem,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    task::{Context, Poll},
+};
 
-use axum::{extract::{FromRequestParts, FromRef, ConnectInfo}, http::request::Parts, async_trait};
-use chrono::{Utc, Timelike};
-use redis::aio::ConnectionManager;
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request},
+    http::{header, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use tower::{Layer, Service};
 
-use crate::error::{CustomResult, CustomError};
+use crate::{
+    cache::RateLimiter,
+    client_ip::ClientIp,
+    error::{CustomError, CustomResult},
+    model::UserQuota,
+    rate_limit::{RateLimitAlgorithm, RateLimitConfig, RateLimitDecision, RateLimitPolicy},
+};
+
+use super::user_service::{AuthenticatedUser, UserService};
 
 const RATE_LIMIT_KEY_PREFIX: &str = "rate_limit";
-const MAX_REQUEST_PER_MINUTE: u64 = 10;
 
 #[derive(Clone)]
 pub struct RateLimitService {
-    redis_connection_manager: ConnectionManager,
+    rate_limiter: Arc<dyn RateLimiter>,
+    config: Arc<RateLimitConfig>,
 }
 
 impl RateLimitService {
-    pub fn new(redis_connection_manager: ConnectionManager) -> Self {
+    pub fn new(rate_limiter: Arc<dyn RateLimiter>, config: RateLimitConfig) -> Self {
         Self {
-            redis_connection_manager
+            rate_limiter,
+            config: Arc::new(config),
         }
     }
 
-    pub async fn assert_rate_limit_not_exceeded(&self, client_addr: &SocketAddr) -> CustomResult<()> {
-        let current_minute = Utc::now().minute();
-        let rate_minute_key = format!("{}:{}:{}", RATE_LIMIT_KEY_PREFIX, client_addr.ip(), current_minute);
-
-        let (count, _): (u64, u64) = redis::pipe()
-            .atomic()
-            .incr(&rate_minute_key, 1)
-            .expire(&rate_minute_key, 60)
-            .query_async(&mut self.redis_connection_manager.clone())
-            .await?;
-
-        if count > MAX_REQUEST_PER_MINUTE {
-            return Err(CustomError::TooManyRequests {
-                actual_count: count,
-                permission_count: MAX_REQUEST_PER_MINUTE,
-            });
+    // authenticated users are limited by their quotas, if they have any, otherwise by
+    // policies of routes like anonymous clients, but with a budget of their own
+    pub async fn assert_rate_limit_not_exceeded(
+        &self,
+        method: &Method,
+        path: &str,
+        client_ip: IpAddr,
+        authenticated_user: Option<&AuthenticatedUser>,
+    ) -> CustomResult<RateLimitDecision> {
+        let mut limits = authenticated_user
+            .into_iter()
+            .flat_map(|AuthenticatedUser { username, user }| {
+                get_quota_policies(user.quota.as_ref()).map(move |(period, policy)| {
+                    let key = format!(
+                        "{}:quota:{}:user:{}",
+                        RATE_LIMIT_KEY_PREFIX, period, username
+                    );
+
+                    (key, policy)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if limits.is_empty() {
+            let (policy_key, policy) = self.config.get_policy(method, path);
+            let client = match authenticated_user {
+                Some(AuthenticatedUser { username, .. }) => format!("user:{}", username),
+                None => client_ip.to_string(),
+            };
+
+            limits.push((
+                format!("{}:{}:{}", RATE_LIMIT_KEY_PREFIX, policy_key, client),
+                policy,
+            ));
         }
 
-        Ok(())
+        // the most restrictive limit is reported to a client
+        let mut result = None;
+
+        for (rate_limit_key, policy) in limits {
+            let decision = self
+                .rate_limiter
+                .rate_limit(&rate_limit_key, &policy)
+                .await?;
+            log::debug!(
+                "{}: {} of {} requests remaining, reset in {} ms",
+                rate_limit_key,
+                decision.remaining,
+                decision.limit,
+                decision.reset_after_ms
+            );
+
+            if !decision.is_allowed {
+                log::info!(
+                    "{}: rate limit exceeded, retry in {} ms",
+                    rate_limit_key,
+                    decision.retry_after_ms
+                );
+
+                return Err(CustomError::TooManyRequests {
+                    limit: decision.limit,
+                    reset_after_secs: decision.reset_after_secs(),
+                    retry_after_secs: decision.retry_after_secs(),
+                });
+            }
+
+            if result.map_or(true, |result: RateLimitDecision| {
+                decision.remaining < result.remaining
+            }) {
+                result = Some(decision);
+            }
+        }
+
+        Ok(result.expect("There is at least one rate limit"))
+    }
+}
+
+// a minute is counted precisely, and a day is refilled evenly, so it needs no log of requests;
+// a zero quota is the same as no quota
+fn get_quota_policies(
+    quota: Option<&UserQuota>,
+) -> impl Iterator<Item = (&'static str, RateLimitPolicy)> {
+    let (per_minute, per_day) = quota.map_or((None, None), |q| (q.per_minute, q.per_day));
+
+    [
+        ("minute", per_minute, RateLimitAlgorithm::SlidingWindow, 60),
+        (
+            "day",
+            per_day,
+            RateLimitAlgorithm::TokenBucket,
+            24 * 60 * 60,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(period, limit, algorithm, period_secs)| {
+        let policy = RateLimitPolicy {
+            algorithm,
+            limit: limit.filter(|limit| *limit > 0)?,
+            period_secs,
+        };
+
+        Some((period, policy))
+    })
+}
+
+// applies policies of `RateLimitService` to every route of a router, the state of
+// the limit is returned to a client in headers of the response
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    rate_limit_service: RateLimitService,
+    user_service: UserService,
+}
+
+impl RateLimitLayer {
+    pub fn new(rate_limit_service: RateLimitService, user_service: UserService) -> Self {
+        Self {
+            rate_limit_service,
+            user_service,
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitMiddleware {
+            inner,
+            rate_limit_service: self.rate_limit_service.clone(),
+            user_service: self.user_service.clone(),
+        }
     }
 }
 
-pub struct RateLimit;
+#[derive(Clone)]
+pub struct RateLimitMiddleware<S> {
+    inner: S,
+    rate_limit_service: RateLimitService,
+    user_service: UserService,
+}
 
-#[async_trait]
-impl<S> FromRequestParts<S> for RateLimit
+impl<S> Service<Request> for RateLimitMiddleware<S>
 where
-    RateLimitService: FromRef<S>,
-    S: Send + Sync,
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
 {
-    type Rejection = CustomError;
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        // the service which has been polled ready is taken, and its clone is left instead
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+        let rate_limit_service = self.rate_limit_service.clone();
+        let user_service = self.user_service.clone();
+
+        Box::pin(async move {
+            // requests are never let through unlimited, even if `ClientIpLayer` is missing
+            let client_ip = match request.extensions().get::<ClientIp>() {
+                Some(&ClientIp(client_ip)) => client_ip,
+                None => match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+                    Some(&ConnectInfo(addr)) => {
+                        log::error!(
+                            "Client IP of {} isn't resolved, peer address {} is used instead",
+                            request.uri(),
+                            addr
+                        );
+
+                        addr.ip()
+                    }
+                    None => {
+                        log::error!(
+                            "Neither client IP nor peer address of {} is known",
+                            request.uri()
+                        );
+
+                        return Ok((
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            String::from("Something went wrong"),
+                        )
+                            .into_response());
+                    }
+                },
+            };
+
+            let authorization = request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|header| header.to_str().ok());
+
+            // routes are identified by their pattern, so all planets share the same policy
+            let path = request.extensions().get::<MatchedPath>().map_or_else(
+                || request.uri().path(),
+                |matched_path| matched_path.as_str(),
+            );
+
+            let (authenticated_user, decision) = match limit_request(
+                &rate_limit_service,
+                &user_service,
+                request.method(),
+                path,
+                client_ip,
+                authorization,
+            )
+            .await
+            {
+                Ok(result) => result,
+                Err(err) => return Ok(err.into_response()),
+            };
 
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let ConnectInfo(addr) = parts.extensions.get().unwrap();
+            // so the credentials aren't verified once again
+            if let Some(authenticated_user) = authenticated_user {
+                request.extensions_mut().insert(authenticated_user);
+            }
 
-        RateLimitService::from_ref(state)
-            .assert_rate_limit_not_exceeded(addr)
-            .await?;
+            let mut response = inner.call(request).await?;
+            response.headers_mut().extend(decision.headers());
 
-        Ok(Self)
+            Ok(response)
+        })
+    }
+}
+
+// credentials which haven't been verified recently are checked only within the limit of
+// the address, so bogus ones can't make the server hash passwords without any limit
+async fn limit_request(
+    rate_limit_service: &RateLimitService,
+    user_service: &UserService,
+    method: &Method,
+    path: &str,
+    client_ip: IpAddr,
+    authorization: Option<&str>,
+) -> CustomResult<(Option<AuthenticatedUser>, RateLimitDecision)> {
+    let verified_user = match authorization {
+        Some(authorization) => user_service.get_verified(authorization).await?,
+        None => None,
+    };
+
+    let authorization = match authorization {
+        Some(authorization) if verified_user.is_none() => authorization,
+        _ => {
+            let decision = rate_limit_service
+                .assert_rate_limit_not_exceeded(method, path, client_ip, verified_user.as_ref())
+                .await?;
+
+            return Ok((verified_user, decision));
+        }
+    };
+
+    let decision = rate_limit_service
+        .assert_rate_limit_not_exceeded(method, path, client_ip, None)
+        .await?;
+
+    // wrong credentials are rejected by routes which require them, and here
+    // such a client is considered anonymous
+    match user_service.authenticate(authorization).await {
+        Ok(authenticated_user) => {
+            let decision = rate_limit_service
+                .assert_rate_limit_not_exceeded(method, path, client_ip, Some(&authenticated_user))
+                .await?;
+
+            Ok((Some(authenticated_user), decision))
+        }
+        Err(err) => {
+            log::debug!("Failed to authenticate a client {}: {}", client_ip, err);
+
+            Ok((None, decision))
+        }
     }
 }
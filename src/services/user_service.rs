@@ -1,16 +1,120 @@
-use crate::{db::MongoDbClient, error::CustomResult, model::User};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use http_auth_basic::Credentials;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    db::UserRepository,
+    error::{CustomError, CustomResult},
+    model::User,
+};
+
+const VERIFIED_CREDENTIALS_TTL: Duration = Duration::from_secs(60);
+
+// hashes of Authorization headers, and password hashes which they have been verified against
+type VerifiedCredentials = HashMap<Vec<u8>, (String, Instant)>;
+
+// a user whose credentials have been verified while handling a request
+#[derive(Clone)]
+pub struct AuthenticatedUser {
+    pub username: String,
+    pub user: User,
+}
 
 #[derive(Clone)]
 pub struct UserService {
-    mongodb_client: MongoDbClient,
+    user_repository: Arc<dyn UserRepository>,
+    // verification is slow on purpose, so successful ones are kept for a while,
+    // keyed by a hash of the header rather than the password itself
+    verified_credentials: Arc<Mutex<VerifiedCredentials>>,
 }
 
 impl UserService {
-    pub fn new(mongodb_client: MongoDbClient) -> Self {
-        Self { mongodb_client }
+    pub fn new(user_repository: Arc<dyn UserRepository>) -> Self {
+        Self {
+            user_repository,
+            verified_credentials: Arc::new(Mutex::new(HashMap::new())),
+        }
     }
 
     pub async fn get_user(&self, username: String) -> CustomResult<User> {
-        self.mongodb_client.get_user(username).await
+        self.user_repository.get_user(username).await
+    }
+
+    // credentials which have been verified recently, it's cheap unlike `authenticate`; the user
+    // is read anew, so a changed access, quota or password takes effect at once
+    pub async fn get_verified(
+        &self,
+        authorization: &str,
+    ) -> CustomResult<Option<AuthenticatedUser>> {
+        let Some(password_hash) = self
+            .lock_verified_credentials()
+            .get(&get_credentials_key(authorization))
+            .filter(|(_, verified_at)| verified_at.elapsed() < VERIFIED_CREDENTIALS_TTL)
+            .map(|(password_hash, _)| password_hash.clone())
+        else {
+            return Ok(None);
+        };
+
+        let username = Credentials::from_header(authorization.to_string())?.user_id;
+
+        let user = match self.get_user(username.clone()).await {
+            Ok(user) => user,
+            Err(CustomError::UserNotFound { .. }) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        if user.password != password_hash {
+            return Ok(None);
+        }
+
+        Ok(Some(AuthenticatedUser { username, user }))
+    }
+
+    pub async fn authenticate(&self, authorization: &str) -> CustomResult<AuthenticatedUser> {
+        if let Some(authenticated_user) = self.get_verified(authorization).await? {
+            return Ok(authenticated_user);
+        }
+
+        let Credentials {
+            user_id: username,
+            password,
+        } = Credentials::from_header(authorization.to_string())?;
+
+        let user = self.get_user(username.clone()).await?;
+
+        // hashing is slow on purpose, so it shouldn't block the runtime
+        let password_hash = user.password.clone();
+        tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&password_hash).and_then(|parsed_hash| {
+                Argon2::default().verify_password(password.as_bytes(), &parsed_hash)
+            })
+        })
+        .await??;
+
+        let mut verified_credentials = self.lock_verified_credentials();
+        verified_credentials
+            .retain(|_, (_, verified_at)| verified_at.elapsed() < VERIFIED_CREDENTIALS_TTL);
+        verified_credentials.insert(
+            get_credentials_key(authorization),
+            (user.password.clone(), Instant::now()),
+        );
+
+        Ok(AuthenticatedUser { username, user })
+    }
+
+    fn lock_verified_credentials(&self) -> MutexGuard<'_, VerifiedCredentials> {
+        self.verified_credentials
+            .lock()
+            .expect("Verified credentials lock is poisoned")
     }
 }
+
+fn get_credentials_key(authorization: &str) -> Vec<u8> {
+    Sha256::digest(authorization.as_bytes()).to_vec()
+}